@@ -0,0 +1,232 @@
+use core::{mem, ptr};
+
+use x86_64::{PhysAddr, VirtAddr};
+
+// the bootloader maps all physical memory, so the tables are read through
+// the physical memory offset and never mapped on their own
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+const EBDA_POINTER: u64 = 0x40e;
+const BIOS_AREA_START: u64 = 0xe_0000;
+const BIOS_AREA_END: u64 = 0x10_0000;
+
+const MAX_IO_APICS: usize = 8;
+const MAX_OVERRIDES: usize = 16;
+
+// the local APIC address and flags come first, then the entries
+const MADT_ENTRIES_OFFSET: usize = mem::size_of::<SdtHeader>() + 8;
+const MADT_IO_APIC: u8 = 1;
+const MADT_INTERRUPT_OVERRIDE: u8 = 2;
+const MADT_LOCAL_APIC_OVERRIDE: u8 = 5;
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // the fields below exist from revision 2 on
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3]
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    NoRsdp,
+    BadChecksum([u8; 4]),
+    // the table claims to be shorter than what it has to hold
+    BadLength([u8; 4]),
+    NoMadt
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub address: PhysAddr,
+    // the first global system interrupt this I/O APIC handles
+    pub gsi_base: u32
+}
+
+// where an IRQ ends up on the I/O APICs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqRoute {
+    pub gsi: u32,
+    pub active_low: bool,
+    pub level_triggered: bool
+}
+
+#[derive(Debug, Clone, Copy)]
+struct InterruptOverride {
+    irq: u8,
+    route: IrqRoute
+}
+
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic: PhysAddr,
+    io_apics: [Option<IoApicEntry>; MAX_IO_APICS],
+    overrides: [Option<InterruptOverride>; MAX_OVERRIDES]
+}
+
+impl Madt {
+    pub fn io_apics(&self) -> impl Iterator<Item = &IoApicEntry> {
+        self.io_apics.iter().flatten()
+    }
+
+    // ISA interrupts are edge triggered and active high unless the firmware
+    // says otherwise
+    pub fn isa_route(&self, irq: u8) -> IrqRoute {
+        self.overrides.iter().flatten()
+            .find(|over| over.irq == irq)
+            .map(|over| over.route)
+            .unwrap_or(IrqRoute { gsi: u32::from(irq), active_low: false, level_triggered: false })
+    }
+}
+
+pub unsafe fn find_madt(physical_memory_offset: VirtAddr) -> Result<Madt, AcpiError> {
+    let tables = Tables { physical_memory_offset };
+    let rsdp = tables.find_rsdp().ok_or(AcpiError::NoRsdp)?;
+    let madt = tables.find_table(&rsdp, MADT_SIGNATURE)?.ok_or(AcpiError::NoMadt)?;
+    tables.parse_madt(madt)
+}
+
+struct Tables {
+    physical_memory_offset: VirtAddr
+}
+
+impl Tables {
+    unsafe fn read<T: Copy>(&self, addr: u64) -> T {
+        ptr::read_unaligned((self.physical_memory_offset + addr).as_ptr())
+    }
+
+    unsafe fn checksum(&self, addr: u64, len: u64) -> u8 {
+        (0..len).fold(0u8, |sum, offset| sum.wrapping_add(self.read::<u8>(addr + offset)))
+    }
+
+    // the RSDP sits in the first KiB of the EBDA or in the BIOS area below
+    // 1 MiB, always on a 16 byte boundary
+    unsafe fn find_rsdp(&self) -> Option<Rsdp> {
+        let ebda = u64::from(self.read::<u16>(EBDA_POINTER)) << 4;
+        let ebda_area = (ebda..ebda + 1024).step_by(16).filter(|_| ebda != 0);
+        let bios_area = (BIOS_AREA_START..BIOS_AREA_END).step_by(16);
+        ebda_area.chain(bios_area).find_map(|addr| {
+            let rsdp = self.read::<Rsdp>(addr);
+            if &rsdp.signature != RSDP_SIGNATURE || self.checksum(addr, 20) != 0 {
+                return None;
+            }
+            if rsdp.revision >= 2 && self.checksum(addr, u64::from(rsdp.length)) != 0 {
+                return None;
+            }
+            Some(rsdp)
+        })
+    }
+
+    // returns the physical address of the table with `signature`
+    unsafe fn find_table(&self, rsdp: &Rsdp, signature: &[u8; 4])
+        -> Result<Option<u64>, AcpiError> {
+        let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+            (rsdp.xsdt_address, 8)
+        } else {
+            (u64::from(rsdp.rsdt_address), 4)
+        };
+        let header = self.checked_header(root)?;
+        let entries = (u64::from(header.length) - mem::size_of::<SdtHeader>() as u64) / entry_size;
+        for idx in 0..entries {
+            let entry = root + mem::size_of::<SdtHeader>() as u64 + idx * entry_size;
+            let table = if entry_size == 8 {
+                self.read::<u64>(entry)
+            } else {
+                u64::from(self.read::<u32>(entry))
+            };
+            if &self.read::<SdtHeader>(table).signature == signature {
+                self.checked_header(table)?;
+                return Ok(Some(table));
+            }
+        }
+        Ok(None)
+    }
+
+    unsafe fn checked_header(&self, addr: u64) -> Result<SdtHeader, AcpiError> {
+        let header = self.read::<SdtHeader>(addr);
+        if (header.length as usize) < mem::size_of::<SdtHeader>() {
+            return Err(AcpiError::BadLength(header.signature));
+        }
+        if self.checksum(addr, u64::from(header.length)) != 0 {
+            return Err(AcpiError::BadChecksum(header.signature));
+        }
+        Ok(header)
+    }
+
+    unsafe fn parse_madt(&self, addr: u64) -> Result<Madt, AcpiError> {
+        let header = self.checked_header(addr)?;
+        if (header.length as usize) < MADT_ENTRIES_OFFSET {
+            return Err(AcpiError::BadLength(header.signature));
+        }
+        let mut madt = Madt {
+            local_apic: PhysAddr::new(u64::from(self.read::<u32>(addr + 36))),
+            io_apics: [None; MAX_IO_APICS],
+            overrides: [None; MAX_OVERRIDES]
+        };
+        let end = addr + u64::from(header.length);
+        let mut entry = addr + MADT_ENTRIES_OFFSET as u64;
+        while entry + 2 <= end {
+            let kind = self.read::<u8>(entry);
+            let len = u64::from(self.read::<u8>(entry + 1));
+            if len < 2 || entry + len > end {
+                break;
+            }
+            match kind {
+                MADT_IO_APIC => {
+                    let io_apic = IoApicEntry {
+                        id: self.read(entry + 2),
+                        address: PhysAddr::new(u64::from(self.read::<u32>(entry + 4))),
+                        gsi_base: self.read(entry + 8)
+                    };
+                    if let Some(slot) = madt.io_apics.iter_mut().find(|slot| slot.is_none()) {
+                        *slot = Some(io_apic);
+                    }
+                }
+                MADT_INTERRUPT_OVERRIDE => {
+                    // bits 0-1 are the polarity and 2-3 the trigger mode,
+                    // 0 means the bus default and 3 low or level
+                    let flags = self.read::<u16>(entry + 8);
+                    let over = InterruptOverride {
+                        irq: self.read(entry + 3),
+                        route: IrqRoute {
+                            gsi: self.read(entry + 4),
+                            active_low: flags & 0b11 == 0b11,
+                            level_triggered: (flags >> 2) & 0b11 == 0b11
+                        }
+                    };
+                    if let Some(slot) = madt.overrides.iter_mut().find(|slot| slot.is_none()) {
+                        *slot = Some(over);
+                    }
+                }
+                MADT_LOCAL_APIC_OVERRIDE => {
+                    madt.local_apic = PhysAddr::new(self.read(entry + 4));
+                }
+                _ => {}
+            }
+            entry += len;
+        }
+        Ok(madt)
+    }
+}
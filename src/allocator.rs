@@ -1,37 +1,100 @@
 pub mod bump;
 pub mod linked_list;
 pub mod fixed_size_block;
+pub mod slab;
+pub mod stats;
+pub mod oom;
+#[cfg(feature = "heap-debug")]
+pub mod debug;
+#[cfg(feature = "heap-leak-tracker")]
+pub mod leak;
 
-use core::{alloc::{GlobalAlloc, Layout}, ptr::null_mut};
-//use linked_list_allocator::LockedHeap;
-use x86_64::{VirtAddr, structures::paging::{Page, Size4KiB, FrameAllocator, mapper::MapToError, PageTableFlags, Mapper}};
+use core::{alloc::{GlobalAlloc, Layout}, fmt::Write, ptr::{self, null_mut}, sync::atomic::{AtomicUsize, Ordering}};
+use x86_64::{VirtAddr, structures::paging::{Page, PageSize, Size4KiB, FrameAllocator, mapper::MapToError, PageTableFlags, Mapper}};
 
-use self::{bump::Locked, fixed_size_block::FixedSizeBlockAllocator};
+use crate::memory::{self, KernelMemory, vma::{self, VmaKind}};
+#[cfg(feature = "heap-leak-tracker")]
+use crate::serial_println;
+use self::stats::HeapStats;
+#[cfg(not(feature = "alloc-external"))]
+use self::bump::Locked;
 
+const HEAP_BACKENDS: usize = cfg!(feature = "alloc-bump") as usize
+    + cfg!(feature = "alloc-linked-list") as usize
+    + cfg!(feature = "alloc-fixed-block") as usize
+    + cfg!(feature = "alloc-slab") as usize
+    + cfg!(feature = "alloc-external") as usize;
+
+const _: () = assert!(HEAP_BACKENDS == 1, "enable exactly one `alloc-*` heap backend, the \
+    default one is `alloc-fixed-block`, pass `--no-default-features` to pick another");
+
+#[cfg(feature = "alloc-bump")]
+static ALLOCATOR: Locked<bump::BumpAllocator> = Locked::new(bump::BumpAllocator::new());
+
+#[cfg(feature = "alloc-linked-list")]
+static ALLOCATOR: Locked<linked_list::LinkedListAllocator> =
+    Locked::new(linked_list::LinkedListAllocator::new());
+
+#[cfg(feature = "alloc-fixed-block")]
+static ALLOCATOR: Locked<fixed_size_block::FixedSizeBlockAllocator> =
+    Locked::new(fixed_size_block::FixedSizeBlockAllocator::new());
+
+#[cfg(feature = "alloc-slab")]
+static ALLOCATOR: Locked<slab::SlabAllocator> = Locked::new(slab::SlabAllocator::new());
+
+#[cfg(feature = "alloc-external")]
+static ALLOCATOR: linked_list_allocator::LockedHeap = linked_list_allocator::LockedHeap::empty();
+
+// checks every allocation before it reaches the backend
+#[cfg(feature = "heap-debug")]
+static DEBUG_ALLOCATOR: debug::DebugAllocator = debug::DebugAllocator::new(&ALLOCATOR);
+
+// records every live allocation, above the debug layer so it sees the
+// requested layouts
+#[cfg(all(feature = "heap-leak-tracker", feature = "heap-debug"))]
+static LEAK_TRACKER: leak::LeakTracker = leak::LeakTracker::new(&DEBUG_ALLOCATOR);
+
+#[cfg(all(feature = "heap-leak-tracker", not(feature = "heap-debug")))]
+static LEAK_TRACKER: leak::LeakTracker = leak::LeakTracker::new(&ALLOCATOR);
+
+#[cfg(feature = "heap-leak-tracker")]
 #[global_allocator]
-//static ALLOCATOR: Dummy = Dummy;
-//static ALLOCATOR: Locked<BumpAllocator> = Locked::new(BumpAllocator::new());
-//static ALLOCATOR: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static GLOBAL_ALLOCATOR: oom::ReclaimingAllocator = oom::ReclaimingAllocator::new(&LEAK_TRACKER);
 
-pub struct Dummy;
+#[cfg(all(feature = "heap-debug", not(feature = "heap-leak-tracker")))]
+#[global_allocator]
+static GLOBAL_ALLOCATOR: oom::ReclaimingAllocator = oom::ReclaimingAllocator::new(&DEBUG_ALLOCATOR);
 
-unsafe impl GlobalAlloc for Dummy {
-    unsafe fn alloc(&self, _layout: core::alloc::Layout) -> *mut u8 {
-        null_mut()
-    }
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {
-        panic!("dealloc should never be called");
-    }
-}
+#[cfg(not(any(feature = "heap-debug", feature = "heap-leak-tracker")))]
+#[global_allocator]
+static GLOBAL_ALLOCATOR: oom::ReclaimingAllocator = oom::ReclaimingAllocator::new(&ALLOCATOR);
 
+// only reached once the reclaimers could not free enough memory
 #[alloc_error_handler]
 fn alloc_error_handler(layout: Layout) -> ! {
-    panic!("allocation error: {:#?}", layout)
+    x86_64::instructions::interrupts::disable();
+    let report = oom::OomReport { layout };
+    // the allocation may have failed in code that is printing
+    if let Some(mut serial) = crate::serial::SERIAL1.try_lock() {
+        let _ = writeln!(serial, "{}", report);
+    }
+    if let Some(mut writer) = crate::vga_buffer::WRITER.try_lock() {
+        let _ = writeln!(writer, "{}", report);
+    }
+    // the panic handler decides what happens next, test kernels fail there
+    panic!("out of memory allocating {} bytes", layout.size());
 }
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024;
+// the whole virtual range the heap is allowed to grow into, every backend
+// but `alloc-external` maps more of it when it runs out
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024;
+const HEAP_GROW_MIN: usize = 64 * 1024;
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+static HEAP_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
+static HEAP_COMMITTED: AtomicUsize = AtomicUsize::new(0);
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
@@ -43,20 +106,191 @@ pub fn init_heap(
     let heap_end_page: Page<Size4KiB> = Page::containing_address(heap_end);
     let heap_page_range = Page::range_inclusive(heap_start_page, heap_end_page);
     for page in heap_page_range {
-        let frame = frame_allocator.allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe {
-            mapper.map_to(page, frame, flags, frame_allocator)?.flush();
-        }
+        map_heap_page(page, mapper, frame_allocator)?;
     }
+    // the heap never grows past HEAP_MAX_SIZE, so both neighbours stay unmapped
+    let guard_size = PAGE_SIZE as u64;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    vma::reserve("heap", heap_start, HEAP_MAX_SIZE as u64, flags, VmaKind::Mapped)
+        .expect("heap range is already in use");
+    for &guard_start in [heap_start - guard_size, heap_start + HEAP_MAX_SIZE].iter() {
+        vma::reserve("heap", guard_start, guard_size, PageTableFlags::empty(), VmaKind::Guard)
+            .expect("heap guard range is already in use");
+    }
+    HEAP_COMMITTED.store(HEAP_SIZE, Ordering::Relaxed);
     unsafe {
         ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
     }
+    #[cfg(feature = "alloc-fixed-block")]
+    oom::register_reclaimer("fixed-block free lists", || ALLOCATOR.lock().release_free_blocks());
+    #[cfg(feature = "alloc-slab")]
+    oom::register_reclaimer("empty slabs", || ALLOCATOR.lock().release_empty_slabs());
+    Ok(())
+}
+
+fn map_heap_page(
+    page: Page<Size4KiB>,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let frame = frame_allocator.allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    unsafe {
+        mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+    }
     Ok(())
 }
 
-fn align_up(addr: usize, align: usize) -> usize {
+pub fn set_heap_limit(limit: usize) {
+    HEAP_LIMIT.store(limit.min(HEAP_MAX_SIZE), Ordering::Relaxed);
+}
+
+pub fn heap_limit() -> usize {
+    HEAP_LIMIT.load(Ordering::Relaxed)
+}
+
+pub fn heap_committed() -> usize {
+    HEAP_COMMITTED.load(Ordering::Relaxed)
+}
+
+pub fn stats() -> HeapStats {
+    let mut stats = backend_stats(true).expect("stats waits for the heap lock");
+    stats.committed = heap_committed();
+    stats
+}
+
+// for reports made while the heap may be locked, gives up instead of waiting
+pub fn try_stats() -> Option<HeapStats> {
+    let mut stats = backend_stats(false)?;
+    stats.committed = heap_committed();
+    Some(stats)
+}
+
+// the typed way into the named slab caches, plain allocations of the same
+// size stay in the size classes
+#[cfg(feature = "alloc-slab")]
+pub fn alloc_cached<T: slab::CachedObject>(value: T) -> Result<ptr::NonNull<T>, T> {
+    ALLOCATOR.lock().alloc_typed(value)
+}
+
+#[cfg(feature = "alloc-slab")]
+pub unsafe fn free_cached<T: slab::CachedObject>(object: ptr::NonNull<T>) -> T {
+    ALLOCATOR.lock().free_typed(object)
+}
+
+#[cfg(feature = "alloc-slab")]
+pub fn slab_cache_stats(id: slab::CacheId) -> slab::CacheStats {
+    ALLOCATOR.lock().cache_stats_of(id)
+}
+
+#[cfg(feature = "heap-leak-tracker")]
+pub fn dump_live_allocations() {
+    serial_println!("live allocations:");
+    LEAK_TRACKER.dump_since(0);
+}
+
+// runs `f` and returns how many of its allocations are still live, after
+// printing them
+#[cfg(feature = "heap-leak-tracker")]
+pub fn count_leaks<F: FnOnce()>(f: F) -> usize {
+    let seq = LEAK_TRACKER.next_seq();
+    f();
+    let leaks = LEAK_TRACKER.count_since(seq);
+    if leaks > 0 {
+        serial_println!("leaked allocations:");
+        LEAK_TRACKER.dump_since(seq);
+    }
+    leaks
+}
+
+// panics if `f` leaves anything allocated behind, without the leak tracker
+// it only runs `f`
+pub fn assert_no_leaks<F: FnOnce()>(f: F) {
+    #[cfg(feature = "heap-leak-tracker")]
+    {
+        let leaks = count_leaks(f);
+        if leaks > 0 {
+            panic!("{} allocations leaked", leaks);
+        }
+    }
+    #[cfg(not(feature = "heap-leak-tracker"))]
+    f();
+}
+
+#[cfg(not(feature = "alloc-external"))]
+fn backend_stats(wait: bool) -> Option<HeapStats> {
+    let mut allocator = if wait { ALLOCATOR.lock() } else { ALLOCATOR.try_lock()? };
+    Some(allocator.stats())
+}
+
+// the external heap keeps no counters of its own
+#[cfg(feature = "alloc-external")]
+fn backend_stats(wait: bool) -> Option<HeapStats> {
+    let heap = if wait { ALLOCATOR.lock() } else { ALLOCATOR.try_lock()? };
+    Some(HeapStats::new(stats::Counters::new(), heap.free(), None))
+}
+
+// maps at least `min_size` more bytes right above `heap_top` and returns how
+// many bytes were actually mapped
+fn grow_heap(heap_top: usize, min_size: usize) -> Option<usize> {
+    let committed = HEAP_COMMITTED.load(Ordering::Relaxed);
+    // allocators set up on some other memory, like in the unit tests, stay
+    // the size they are
+    if heap_top != HEAP_START + committed {
+        return None;
+    }
+    let available = heap_limit().saturating_sub(committed);
+    let size = align_up(min_size.max(HEAP_GROW_MIN), PAGE_SIZE).min(available);
+    if size < min_size {
+        return None;
+    }
+
+    // whoever holds the kernel memory does not allocate, so it can be waited for
+    let mut memory = memory::kernel_memory()?;
+    let KernelMemory { mapper, frame_allocator } = &mut *memory;
+    let start_page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(heap_top as u64));
+    let mut mapped = 0;
+    for page in Page::range(start_page, start_page + (size / PAGE_SIZE) as u64) {
+        if map_heap_page(page, mapper, frame_allocator).is_err() {
+            break;
+        }
+        mapped += PAGE_SIZE;
+    }
+    if mapped == 0 {
+        return None;
+    }
+    HEAP_COMMITTED.fetch_add(mapped, Ordering::Relaxed);
+    Some(mapped)
+}
+
+// allocates from a linked_list_allocator heap, growing it when it is exhausted
+fn heap_alloc(heap: &mut linked_list_allocator::Heap, layout: Layout) -> *mut u8 {
+    loop {
+        if let Ok(ptr) = heap.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+        match grow_heap(heap.top(), layout.size() + layout.align()) {
+            Some(size) => unsafe { heap.extend(size) },
+            None => return null_mut()
+        }
+    }
+}
+
+// the last resort for realloc: move the data to a fresh allocation
+unsafe fn realloc_by_copy(
+    allocator: &impl GlobalAlloc, ptr: *mut u8, layout: Layout, new_size: usize
+) -> *mut u8 {
+    let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+    let new_ptr = allocator.alloc(new_layout);
+    if !new_ptr.is_null() {
+        ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+        allocator.dealloc(ptr, layout);
+    }
+    new_ptr
+}
+
+const fn align_up(addr: usize, align: usize) -> usize {
     /*
      *let remainder = addr % align;
      *if remainder == 0 { addr } else { addr - remainder + align }
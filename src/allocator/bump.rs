@@ -1,13 +1,14 @@
 use core::{alloc::GlobalAlloc, ptr::null_mut};
 use spin;
 
-use super::align_up;
+use super::{align_up, stats::{Counters, HeapStats}};
 
 pub struct BumpAllocator {
     heap_start: usize,
     heap_end: usize,
     next: usize,
-    n_allocations: usize
+    n_allocations: usize,
+    counters: Counters
 }
 
 pub struct Locked<T> {
@@ -22,11 +23,17 @@ impl<T> Locked<T> {
     pub fn lock(&self) -> spin::MutexGuard<T> {
         self.inner.lock()
     }
+
+    pub fn try_lock(&self) -> Option<spin::MutexGuard<T>> {
+        self.inner.try_lock()
+    }
 }
 
 impl BumpAllocator {
     pub const fn new() -> Self {
-        BumpAllocator { heap_start: 0, heap_end: 0, next: 0, n_allocations: 0 }
+        BumpAllocator {
+            heap_start: 0, heap_end: 0, next: 0, n_allocations: 0, counters: Counters::new()
+        }
     }
 
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
@@ -34,6 +41,11 @@ impl BumpAllocator {
         self.heap_end = heap_start + heap_size;
         self.next = heap_start;
     }
+
+    pub fn stats(&mut self) -> HeapStats {
+        let free = self.heap_end - self.next;
+        HeapStats::new(self.counters, free, Some(free))
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
@@ -44,18 +56,22 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
             Some(alloc_end) => alloc_end,
             None => return null_mut()
         };
-        if alloc_end > allocator.heap_end {
-            null_mut()
-        } else {
-            allocator.next = alloc_end;
-            allocator.n_allocations += 1;
-            alloc_start as *mut u8
+        while alloc_end > allocator.heap_end {
+            match super::grow_heap(allocator.heap_end, alloc_end - allocator.heap_end) {
+                Some(size) => allocator.heap_end += size,
+                None => return null_mut()
+            }
         }
+        allocator.next = alloc_end;
+        allocator.n_allocations += 1;
+        allocator.counters.record_alloc(layout);
+        alloc_start as *mut u8
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {
+    unsafe fn dealloc(&self, _ptr: *mut u8, layout: core::alloc::Layout) {
         let mut allocator = self.lock();
         allocator.n_allocations -= 1;
+        allocator.counters.record_dealloc(layout);
         if allocator.n_allocations == 0 {
             allocator.next = allocator.heap_start;
         }
@@ -0,0 +1,140 @@
+use core::{alloc::{GlobalAlloc, Layout}, mem::{align_of, size_of}, ptr::null_mut, sync::atomic::{AtomicUsize, Ordering}};
+
+use crate::serial_println;
+use super::align_up;
+
+pub const ALLOC_POISON: u8 = 0xcd;
+pub const FREE_POISON: u8 = 0xdd;
+pub const RED_ZONE_BYTE: u8 = 0xfd;
+pub const RED_ZONE_SIZE: usize = 16;
+// freed blocks are held back this many frees before the backend gets them,
+// so double frees and writes after free are still visible
+pub const QUARANTINE_LEN: usize = 64;
+
+const LIVE: usize = 0x11fe_11fe_11fe_11fe;
+const FREED: usize = 0xdead_dead_dead_dead;
+
+static VIOLATIONS: AtomicUsize = AtomicUsize::new(0);
+
+// sits right before the front red zone, the state comes last because the
+// backends write their free list links over the start of a block
+struct Header {
+    size: usize,
+    align: usize,
+    state: usize
+}
+
+struct Quarantine {
+    blocks: [usize; QUARANTINE_LEN],
+    next: usize
+}
+
+impl Quarantine {
+    // returns the block that was pushed out to make room
+    fn push(&mut self, ptr: *mut u8) -> Option<*mut u8> {
+        let evicted = self.blocks[self.next];
+        self.blocks[self.next] = ptr as usize;
+        self.next = (self.next + 1) % QUARANTINE_LEN;
+        if evicted == 0 { None } else { Some(evicted as *mut u8) }
+    }
+}
+
+pub struct DebugAllocator {
+    inner: &'static (dyn GlobalAlloc + Sync),
+    quarantine: spin::Mutex<Quarantine>
+}
+
+impl DebugAllocator {
+    pub const fn new(inner: &'static (dyn GlobalAlloc + Sync)) -> Self {
+        DebugAllocator {
+            inner,
+            quarantine: spin::Mutex::new(Quarantine { blocks: [0; QUARANTINE_LEN], next: 0 })
+        }
+    }
+
+    unsafe fn release(&self, ptr: *mut u8) {
+        let header = &*header(ptr);
+        let (size, align) = (header.size, header.align);
+        if !is_filled(ptr, size, FREE_POISON) {
+            report("write after free", ptr, size);
+        }
+        self.inner.dealloc(ptr.sub(front_size(align)), inner_layout(size, align).unwrap());
+    }
+}
+
+pub fn violations() -> usize {
+    VIOLATIONS.load(Ordering::Relaxed)
+}
+
+fn report(what: &str, ptr: *mut u8, size: usize) {
+    VIOLATIONS.fetch_add(1, Ordering::Relaxed);
+    serial_println!("heap-debug: {} at {:#x} ({} bytes)", what, ptr as usize, size);
+}
+
+fn front_size(align: usize) -> usize {
+    align_up(size_of::<Header>() + RED_ZONE_SIZE, align)
+}
+
+fn inner_layout(size: usize, align: usize) -> Option<Layout> {
+    let size = front_size(align).checked_add(size)?.checked_add(RED_ZONE_SIZE)?;
+    Layout::from_size_align(size, align.max(align_of::<Header>())).ok()
+}
+
+unsafe fn header(ptr: *mut u8) -> *mut Header {
+    ptr.sub(RED_ZONE_SIZE + size_of::<Header>()) as *mut Header
+}
+
+unsafe fn is_filled(start: *mut u8, len: usize, byte: u8) -> bool {
+    (0..len).all(|i| *start.add(i) == byte)
+}
+
+unsafe fn check_red_zones(ptr: *mut u8, size: usize) {
+    if !is_filled(ptr.sub(RED_ZONE_SIZE), RED_ZONE_SIZE, RED_ZONE_BYTE) {
+        report("buffer underflow", ptr, size);
+    }
+    if !is_filled(ptr.add(size), RED_ZONE_SIZE, RED_ZONE_BYTE) {
+        report("buffer overflow", ptr, size);
+    }
+}
+
+unsafe impl GlobalAlloc for DebugAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let base = match inner_layout(layout.size(), layout.align()) {
+            Some(inner_layout) => self.inner.alloc(inner_layout),
+            None => return null_mut()
+        };
+        if base.is_null() {
+            return null_mut();
+        }
+        let ptr = base.add(front_size(layout.align()));
+        header(ptr).write(Header { size: layout.size(), align: layout.align(), state: LIVE });
+        ptr.sub(RED_ZONE_SIZE).write_bytes(RED_ZONE_BYTE, RED_ZONE_SIZE);
+        ptr.write_bytes(ALLOC_POISON, layout.size());
+        ptr.add(layout.size()).write_bytes(RED_ZONE_BYTE, RED_ZONE_SIZE);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let header = &mut *header(ptr);
+        match header.state {
+            LIVE => {}
+            FREED => return report("double free", ptr, header.size),
+            _ => return report("free of a block that was never allocated", ptr, layout.size())
+        }
+        if header.size != layout.size() || header.align != layout.align() {
+            VIOLATIONS.fetch_add(1, Ordering::Relaxed);
+            serial_println!(
+                "heap-debug: layout mismatch at {:#x}: allocated {} bytes (align {}), freed as {} bytes (align {})",
+                ptr as usize, header.size, header.align, layout.size(), layout.align()
+            );
+        }
+        check_red_zones(ptr, header.size);
+        ptr.write_bytes(FREE_POISON, header.size);
+        header.state = FREED;
+
+        let evicted = self.quarantine.lock().push(ptr);
+        if let Some(evicted) = evicted {
+            self.release(evicted);
+        }
+    }
+}
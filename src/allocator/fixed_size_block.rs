@@ -1,17 +1,18 @@
-use core::{alloc::{Layout, GlobalAlloc}, ptr::{null_mut, NonNull}, mem::{size_of, align_of}};
+use core::{alloc::{Layout, GlobalAlloc}, ptr::NonNull, mem::{size_of, align_of}};
 
-use super::bump::Locked;
+use super::{bump::Locked, stats::{Counters, HeapStats}};
 
 
 struct Node {
     next: Option<&'static mut Node>
 }
 
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+pub const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
 pub struct FixedSizeBlockAllocator {
     heads: [Option<&'static mut Node>; BLOCK_SIZES.len()],
-    fallback_allocator: linked_list_allocator::Heap
+    fallback_allocator: linked_list_allocator::Heap,
+    counters: Counters
 }
 
 impl FixedSizeBlockAllocator {
@@ -19,7 +20,8 @@ impl FixedSizeBlockAllocator {
         const NONE: Option<&'static mut Node> = None;
         FixedSizeBlockAllocator {
             heads: [NONE; BLOCK_SIZES.len()],
-            fallback_allocator: linked_list_allocator::Heap::empty()
+            fallback_allocator: linked_list_allocator::Heap::empty(),
+            counters: Counters::new()
         }
     }
 
@@ -27,11 +29,37 @@ impl FixedSizeBlockAllocator {
         self.fallback_allocator.init(heap_start, heap_size);
     }
 
-    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
-            Err(_) => null_mut()
+    pub fn stats(&mut self) -> HeapStats {
+        let free = self.fallback_allocator.free();
+        let mut stats = HeapStats::new(self.counters, free, None);
+        for (len, head) in stats.free_list_lengths.iter_mut().zip(self.heads.iter()) {
+            let mut now = head;
+            while let Some(node) = now {
+                *len += 1;
+                now = &node.next;
+            }
+        }
+        stats
+    }
+
+    // hands every block sitting in a free list back to the fallback heap
+    pub fn release_free_blocks(&mut self) -> usize {
+        let mut released = 0;
+        for (idx, head) in self.heads.iter_mut().enumerate() {
+            let layout = Layout::from_size_align(BLOCK_SIZES[idx], BLOCK_SIZES[idx]).unwrap();
+            while let Some(node) = head.take() {
+                *head = node.next.take();
+                unsafe {
+                    self.fallback_allocator.deallocate(NonNull::from(node).cast(), layout);
+                }
+                released += layout.size();
+            }
         }
+        released
+    }
+
+    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        super::heap_alloc(&mut self.fallback_allocator, layout)
     }
 }
 
@@ -43,7 +71,7 @@ fn get_list_index(layout: &Layout) -> Option<usize> {
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match get_list_index(&layout) {
+        let ptr = match get_list_index(&layout) {
             Some(idx) => {
                 match allocator.heads[idx].take() {
                     Some(node) => {
@@ -61,11 +89,16 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
             None => {
                 allocator.fallback_alloc(layout)
             }
+        };
+        if !ptr.is_null() {
+            allocator.counters.record_alloc(layout);
         }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.counters.record_dealloc(layout);
         match get_list_index(&layout) {
             Some(idx) => {
                 let new_node = Node {
@@ -84,4 +117,15 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
             }
         }
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if let Some(idx) = get_list_index(&layout) {
+            if get_list_index(&new_layout) == Some(idx) {
+                self.lock().counters.record_realloc(layout, new_size);
+                return ptr;
+            }
+        }
+        super::realloc_by_copy(self, ptr, layout, new_size)
+    }
 }
@@ -0,0 +1,115 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+use crate::{backtrace, serial_print, serial_println};
+
+pub const MAX_TRACKED: usize = 1024;
+// the innermost frames belong to the allocator itself, the allocation site
+// follows right after them
+pub const BACKTRACE_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LiveAllocation {
+    pub ptr: usize,
+    pub size: usize,
+    pub seq: usize,
+    pub callers: [usize; BACKTRACE_LEN]
+}
+
+impl LiveAllocation {
+    const EMPTY: LiveAllocation = LiveAllocation { ptr: 0, size: 0, seq: 0, callers: [0; BACKTRACE_LEN] };
+}
+
+// kept dense, frees swap the last entry into the hole
+struct LiveTable {
+    entries: [LiveAllocation; MAX_TRACKED],
+    len: usize,
+    next_seq: usize,
+    untracked: usize
+}
+
+pub struct LeakTracker {
+    inner: &'static (dyn GlobalAlloc + Sync),
+    live: spin::Mutex<LiveTable>
+}
+
+impl LeakTracker {
+    pub const fn new(inner: &'static (dyn GlobalAlloc + Sync)) -> Self {
+        LeakTracker {
+            inner,
+            live: spin::Mutex::new(LiveTable {
+                entries: [LiveAllocation::EMPTY; MAX_TRACKED],
+                len: 0,
+                next_seq: 0,
+                untracked: 0
+            })
+        }
+    }
+
+    // sequence number the next allocation will get
+    pub fn next_seq(&self) -> usize {
+        self.live.lock().next_seq
+    }
+
+    pub fn count_since(&self, seq: usize) -> usize {
+        let live = self.live.lock();
+        live.entries[..live.len].iter().filter(|a| a.seq >= seq).count()
+    }
+
+    // prints every live allocation made at or after `seq` and returns how
+    // many there were
+    pub fn dump_since(&self, seq: usize) -> usize {
+        let live = self.live.lock();
+        let mut count = 0;
+        for allocation in live.entries[..live.len].iter().filter(|a| a.seq >= seq) {
+            serial_print!("  {:#x} {} bytes #{}", allocation.ptr, allocation.size, allocation.seq);
+            for &caller in allocation.callers.iter().take_while(|&&caller| caller != 0) {
+                serial_print!(" {:#x}", caller);
+            }
+            serial_println!();
+            count += 1;
+        }
+        if live.untracked > 0 {
+            serial_println!("  {} allocations did not fit in the table", live.untracked);
+        }
+        count
+    }
+}
+
+unsafe impl GlobalAlloc for LeakTracker {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if ptr.is_null() {
+            return ptr;
+        }
+        let mut callers = [0; BACKTRACE_LEN];
+        backtrace::return_addresses(&mut callers);
+
+        let mut live = self.live.lock();
+        let seq = live.next_seq;
+        live.next_seq += 1;
+        if live.len == MAX_TRACKED {
+            live.untracked += 1;
+        } else {
+            let len = live.len;
+            live.entries[len] = LiveAllocation { ptr: ptr as usize, size: layout.size(), seq, callers };
+            live.len += 1;
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        {
+            let mut live = self.live.lock();
+            let len = live.len;
+            // recent allocations tend to be freed first
+            match live.entries[..len].iter().rposition(|a| a.ptr == ptr as usize) {
+                Some(idx) => {
+                    live.entries.swap(idx, len - 1);
+                    live.len -= 1;
+                }
+                None => live.untracked = live.untracked.saturating_sub(1)
+            }
+        }
+        self.inner.dealloc(ptr, layout);
+    }
+}
@@ -2,7 +2,7 @@ use core::{mem::{align_of, size_of}, alloc::{GlobalAlloc, Layout}, ptr::null_mut
 
 use crate::allocator::align_up;
 
-use super::bump::Locked;
+use super::{bump::Locked, stats::{Counters, HeapStats}};
 
 struct Node {
     size: usize,
@@ -23,41 +23,177 @@ impl Node {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitStrategy {
+    FirstFit,
+    BestFit
+}
+
 pub struct LinkedListAllocator {
-    head: Node
+    head: Node,
+    heap_end: usize,
+    strategy: FitStrategy,
+    counters: Counters
 }
 
 impl LinkedListAllocator {
     pub const fn new() -> Self {
-        LinkedListAllocator { head: Node::new(0) }
+        Self::with_strategy(FitStrategy::FirstFit)
+    }
+
+    pub const fn with_strategy(strategy: FitStrategy) -> Self {
+        LinkedListAllocator { head: Node::new(0), heap_end: 0, strategy, counters: Counters::new() }
+    }
+
+    pub fn set_strategy(&mut self, strategy: FitStrategy) {
+        self.strategy = strategy;
     }
 
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.add_free_region(heap_start, heap_size);
+        self.heap_end = heap_start + heap_size;
+    }
+
+    // maps more heap right behind the end, it merges with a free region
+    // that reaches up to there
+    unsafe fn grow(&mut self, size: usize, align: usize) -> bool {
+        match super::grow_heap(self.heap_end, size + align) {
+            Some(grown) => {
+                self.add_free_region(self.heap_end, grown);
+                self.heap_end += grown;
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn stats(&mut self) -> HeapStats {
+        let mut free = 0;
+        let mut largest = 0;
+        let mut now = &self.head;
+        while let Some(ref region) = now.next {
+            free += region.size;
+            largest = largest.max(region.size);
+            now = region;
+        }
+        HeapStats::new(self.counters, free, Some(largest))
     }
 
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         assert_eq!(align_up(addr, align_of::<Node>()), addr);
         assert!(size >= size_of::<Node>());
 
+        // the free list is kept sorted by address so neighbours can be merged
+        let mut now = &mut self.head;
+        loop {
+            match now.next {
+                Some(ref next) if next.start_addr() < addr => {}
+                _ => break
+            }
+            now = now.next.as_mut().unwrap();
+        }
+
         let mut node = Node::new(size);
-        node.next = self.head.next.take();
+        node.next = now.next.take();
         let node_ptr = addr as *mut Node;
         node_ptr.write(node);
-        self.head.next = Some(&mut *node_ptr);
+        now.next = Some(&mut *node_ptr);
+
+        Self::merge_next(now.next.as_mut().unwrap());
+        if now.size > 0 {
+            Self::merge_next(now);
+        }
     }
 
-    fn find_region(&mut self, size: usize, align: usize)
-        -> Option<(&'static mut Node, usize)> {
+    fn merge_next(node: &mut Node) {
+        let end_addr = node.end_addr();
+        match node.next.take() {
+            Some(next) if next.start_addr() == end_addr => {
+                node.size += next.size;
+                node.next = next.next.take();
+            }
+            next => node.next = next
+        }
+    }
+
+    fn take_region_at(&mut self, addr: usize) -> Option<usize> {
         let mut now = &mut self.head;
         while let Some(ref mut region) = now.next {
-            if let Ok(alloc_start) = Self::alloc_from_region(&region, size, align) {
+            if region.start_addr() == addr {
                 let next = region.next.take();
-                let ret = Some((now.next.take().unwrap(), alloc_start));
+                let size = now.next.take().unwrap().size;
                 now.next = next;
-                return ret;
-            } else {
-                now = now.next.as_mut().unwrap();
+                return Some(size);
+            } else if region.start_addr() > addr {
+                return None;
+            }
+            now = now.next.as_mut().unwrap();
+        }
+        None
+    }
+
+    // resizes the block at `addr` without moving it, either by giving its
+    // tail back or by taking from the free region right behind it
+    unsafe fn resize_in_place(&mut self, addr: usize, old_size: usize, new_size: usize) -> bool {
+        let old_end = addr + old_size;
+        let new_end = addr + new_size;
+        if new_size <= old_size {
+            let tail_size = old_size - new_size;
+            if tail_size == 0 {
+                return true;
+            }
+            if tail_size >= size_of::<Node>() {
+                self.add_free_region(new_end, tail_size);
+                return true;
+            }
+        }
+
+        let next_size = match self.take_region_at(old_end) {
+            Some(size) => size,
+            None => return false
+        };
+        let next_end = old_end + next_size;
+        let excess_size = next_end.saturating_sub(new_end);
+        if new_end > next_end || (0 < excess_size && excess_size < size_of::<Node>()) {
+            self.add_free_region(old_end, next_size);
+            return false;
+        }
+        if excess_size > 0 {
+            self.add_free_region(new_end, excess_size);
+        }
+        true
+    }
+
+    fn best_fit_addr(&self, size: usize, align: usize) -> Option<usize> {
+        let mut best: Option<&Node> = None;
+        let mut now = &self.head;
+        while let Some(ref region) = now.next {
+            if Self::alloc_from_region(region, size, align).is_ok()
+                && best.map_or(true, |best| region.size < best.size) {
+                best = Some(region);
+            }
+            now = region;
+        }
+        best.map(|region| region.start_addr())
+    }
+
+    fn find_region(&mut self, size: usize, align: usize)
+        -> Option<(&'static mut Node, usize)> {
+        let target = match self.strategy {
+            FitStrategy::FirstFit => None,
+            FitStrategy::BestFit => Some(self.best_fit_addr(size, align)?)
+        };
+        let mut now = &mut self.head;
+        while let Some(ref mut region) = now.next {
+            let is_target = target.map_or(true, |addr| addr == region.start_addr());
+            match Self::alloc_from_region(&region, size, align) {
+                Ok(alloc_start) if is_target => {
+                    let next = region.next.take();
+                    let ret = Some((now.next.take().unwrap(), alloc_start));
+                    now.next = next;
+                    return ret;
+                }
+                _ => now = now.next.as_mut().unwrap()
             }
         }
         None
@@ -71,6 +207,11 @@ impl LinkedListAllocator {
             return Err(());
         }
 
+        let front_size = alloc_start - region.start_addr();
+        if 0 < front_size && front_size < size_of::<Node>() {
+            return Err(());
+        }
+
         let excess_size = region.end_addr() - alloc_end;
         if 0 < excess_size && excess_size < size_of::<Node>() {
             return Err(());
@@ -91,16 +232,24 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
         let (size, align) = LinkedListAllocator::size_align(layout);
         let mut allocator = self.lock();
 
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
-            let alloc_end = alloc_start.checked_add(size)
-                .expect("overflow");
-            let excess_size = region.end_addr() - alloc_end;
-            if excess_size > 0 {
-                allocator.add_free_region(alloc_end, excess_size);
+        loop {
+            if let Some((region, alloc_start)) = allocator.find_region(size, align) {
+                let alloc_end = alloc_start.checked_add(size)
+                    .expect("overflow");
+                let (region_start, region_end) = (region.start_addr(), region.end_addr());
+                if alloc_start > region_start {
+                    allocator.add_free_region(region_start, alloc_start - region_start);
+                }
+                let excess_size = region_end - alloc_end;
+                if excess_size > 0 {
+                    allocator.add_free_region(alloc_end, excess_size);
+                }
+                allocator.counters.record_alloc(layout);
+                return alloc_start as *mut u8;
+            }
+            if !allocator.grow(size, align) {
+                return null_mut();
             }
-            alloc_start as *mut u8
-        } else {
-            null_mut()
         }
     }
 
@@ -108,5 +257,96 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
         let (size, _align) = LinkedListAllocator::size_align(layout);
         let mut allocator = self.lock();
         allocator.add_free_region(ptr as usize, size);
+        allocator.counters.record_dealloc(layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let (old_size, _) = LinkedListAllocator::size_align(layout);
+        let (size, _) = LinkedListAllocator::size_align(new_layout);
+        {
+            let mut allocator = self.lock();
+            if allocator.resize_in_place(ptr as usize, old_size, size) {
+                allocator.counters.record_realloc(layout, new_size);
+                return ptr;
+            }
+        }
+        super::realloc_by_copy(self, ptr, layout, new_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{alloc::{GlobalAlloc, Layout}, ptr::addr_of_mut};
+    use super::{FitStrategy, LinkedListAllocator, Locked};
+
+    const ARENA_SIZE: usize = 4096;
+
+    #[repr(align(16))]
+    #[allow(dead_code)]
+    struct Arena([u8; ARENA_SIZE]);
+
+    unsafe fn arena_allocator(arena: *mut Arena, strategy: FitStrategy)
+        -> Locked<LinkedListAllocator> {
+        let allocator = Locked::new(LinkedListAllocator::with_strategy(strategy));
+        allocator.lock().init(arena as usize, ARENA_SIZE);
+        allocator
+    }
+
+    #[test_case]
+    fn freed_neighbours_are_merged() {
+        static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+        unsafe {
+            let allocator = arena_allocator(addr_of_mut!(ARENA), FitStrategy::FirstFit);
+            let layout = Layout::from_size_align(1024, 8).unwrap();
+            let blocks = [allocator.alloc(layout), allocator.alloc(layout), allocator.alloc(layout)];
+            allocator.dealloc(blocks[1], layout);
+            allocator.dealloc(blocks[0], layout);
+            allocator.dealloc(blocks[2], layout);
+
+            let stats = allocator.lock().stats();
+            assert_eq!(stats.largest_free_region, Some(ARENA_SIZE));
+            let whole = Layout::from_size_align(ARENA_SIZE, 8).unwrap();
+            assert!(!allocator.alloc(whole).is_null());
+        }
+    }
+
+    #[test_case]
+    fn best_fit_picks_smallest_region() {
+        static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+        unsafe {
+            let allocator = arena_allocator(addr_of_mut!(ARENA), FitStrategy::BestFit);
+            let big = Layout::from_size_align(256, 8).unwrap();
+            let small = Layout::from_size_align(64, 8).unwrap();
+            let a = allocator.alloc(big);
+            let _b = allocator.alloc(small);
+            let c = allocator.alloc(small);
+            let _d = allocator.alloc(small);
+            allocator.dealloc(a, big);
+            allocator.dealloc(c, small);
+
+            assert_eq!(allocator.alloc(small), c);
+        }
+    }
+
+    #[test_case]
+    fn realloc_grows_in_place() {
+        static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+        unsafe {
+            let allocator = arena_allocator(addr_of_mut!(ARENA), FitStrategy::FirstFit);
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let ptr = allocator.alloc(layout);
+            ptr.write_bytes(7, 64);
+            let grown = allocator.realloc(ptr, layout, 1024);
+            assert_eq!(grown, ptr);
+            assert_eq!(*grown.add(63), 7);
+
+            let layout = Layout::from_size_align(1024, 8).unwrap();
+            let blocker = allocator.alloc(Layout::from_size_align(64, 8).unwrap());
+            let moved = allocator.realloc(grown, layout, 2048);
+            assert_ne!(moved, grown);
+            assert!(moved > blocker);
+            assert_eq!(*moved.add(63), 7);
+        }
     }
 }
@@ -0,0 +1,122 @@
+use core::{alloc::{GlobalAlloc, Layout}, fmt, sync::atomic::{AtomicBool, Ordering}};
+
+use crate::{memory, task};
+
+const MAX_RECLAIMERS: usize = 8;
+// give up once the reclaimers keep freeing memory that never suffices
+const MAX_RECLAIM_ROUNDS: usize = 4;
+
+#[derive(Clone, Copy)]
+struct Reclaimer {
+    name: &'static str,
+    // returns how many bytes it gave back
+    reclaim: fn() -> usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReclaimerId(usize);
+
+static RECLAIMERS: spin::Mutex<[Option<Reclaimer>; MAX_RECLAIMERS]> =
+    spin::Mutex::new([None; MAX_RECLAIMERS]);
+static RECLAIMING: AtomicBool = AtomicBool::new(false);
+
+pub fn register_reclaimer(name: &'static str, reclaim: fn() -> usize) -> Option<ReclaimerId> {
+    let mut reclaimers = RECLAIMERS.lock();
+    let idx = reclaimers.iter().position(Option::is_none)?;
+    reclaimers[idx] = Some(Reclaimer { name, reclaim });
+    Some(ReclaimerId(idx))
+}
+
+pub fn unregister_reclaimer(id: ReclaimerId) {
+    RECLAIMERS.lock()[id.0] = None;
+}
+
+// runs every reclaimer once and returns the bytes they gave back together,
+// an allocation failing inside a reclaimer does not start another round
+pub fn reclaim() -> usize {
+    if RECLAIMING.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+    // copied so the reclaimers can free memory without holding the lock
+    let reclaimers = *RECLAIMERS.lock();
+    let freed = reclaimers.iter().flatten().map(|reclaimer| (reclaimer.reclaim)()).sum();
+    RECLAIMING.store(false, Ordering::Release);
+    freed
+}
+
+// outermost layer of the global allocator, retries failed allocations after
+// asking the reclaimers for memory
+pub struct ReclaimingAllocator {
+    inner: &'static (dyn GlobalAlloc + Sync)
+}
+
+impl ReclaimingAllocator {
+    pub const fn new(inner: &'static (dyn GlobalAlloc + Sync)) -> Self {
+        ReclaimingAllocator { inner }
+    }
+
+    fn retry(&self, mut alloc: impl FnMut() -> *mut u8) -> *mut u8 {
+        let mut ptr = alloc();
+        let mut rounds = 0;
+        while ptr.is_null() && rounds < MAX_RECLAIM_ROUNDS && reclaim() > 0 {
+            ptr = alloc();
+            rounds += 1;
+        }
+        ptr
+    }
+}
+
+unsafe impl GlobalAlloc for ReclaimingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.retry(|| self.inner.alloc(layout))
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.retry(|| self.inner.alloc_zeroed(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.retry(|| self.inner.realloc(ptr, layout, new_size))
+    }
+}
+
+// everything worth knowing once memory has run out for good
+pub struct OomReport {
+    pub layout: Layout
+}
+
+impl fmt::Display for OomReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "out of memory: failed to allocate {} bytes (align {})",
+            self.layout.size(), self.layout.align())?;
+        match super::try_stats() {
+            Some(stats) => writeln!(f, "{}", stats)?,
+            None => writeln!(f, "heap: statistics skipped, the allocator is locked")?
+        }
+        // whoever ran out of memory may hold these locks, waiting would hang
+        match memory::try_kernel_memory() {
+            Some(memory) => {
+                let frames = &memory.frame_allocator;
+                writeln!(f, "frames: {} used, {} free of {}",
+                    frames.used_frames(), frames.free_frames(), frames.total_frames())?;
+            }
+            None => writeln!(f, "frames: kernel memory not initialized or locked")?
+        }
+        writeln!(f, "heap limit: {} bytes", super::heap_limit())?;
+        write!(f, "reclaimers:")?;
+        match RECLAIMERS.try_lock() {
+            Some(reclaimers) => {
+                for reclaimer in reclaimers.iter().flatten() {
+                    write!(f, " {}", reclaimer.name)?;
+                }
+            }
+            None => write!(f, " locked")?
+        }
+        writeln!(f)?;
+        write!(f, "tasks: {}", task::task_count())
+    }
+}
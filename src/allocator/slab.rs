@@ -0,0 +1,440 @@
+use core::{alloc::{GlobalAlloc, Layout}, fmt, mem::{align_of, size_of}, ptr::{null_mut, NonNull}};
+
+use crate::task::Task;
+use super::{align_up, bump::Locked, fixed_size_block::BLOCK_SIZES, stats::{Counters, HeapStats}};
+
+const PAGE_SIZE: usize = 4096;
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+const MAX_CACHES: usize = 16;
+
+// `init` creates it right after the size classes
+pub const TASK_CACHE: CacheId = CacheId(BLOCK_SIZES.len());
+
+// kernel types with a cache of their own; `GlobalAlloc` only ever uses the
+// size classes, objects get into these caches through `alloc_typed`
+pub trait CachedObject: Sized {
+    const CACHE: CacheId;
+}
+
+impl CachedObject for Task {
+    const CACHE: CacheId = TASK_CACHE;
+}
+
+struct FreeObject {
+    next: *mut FreeObject
+}
+
+// sits at the start of every slab, slabs are aligned to their size so the
+// slab of an object is found by masking its address
+struct Slab {
+    next: *mut Slab,
+    free: *mut FreeObject,
+    in_use: usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheName {
+    SizeClass(usize),
+    Named(&'static str)
+}
+
+impl fmt::Display for CacheName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheName::SizeClass(size) => write!(f, "size-{}", size),
+            CacheName::Named(name) => write!(f, "{}", name)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SlabCache {
+    name: CacheName,
+    align: usize,
+    object_size: usize,
+    header_size: usize,
+    slab_size: usize,
+    capacity: usize,
+    partial: *mut Slab,
+    full: *mut Slab,
+    // one emptied slab stays around, so an alloc/free pair at a slab
+    // boundary does not go to the page heap every time
+    empty: *mut Slab,
+    slabs: usize,
+    objects_in_use: usize
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub name: CacheName,
+    pub object_size: usize,
+    pub slab_size: usize,
+    pub slabs: usize,
+    pub objects_in_use: usize,
+    pub objects_total: usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheId(usize);
+
+impl SlabCache {
+    const EMPTY: SlabCache = SlabCache::new(CacheName::Named(""), 0, 1);
+
+    const fn new(name: CacheName, size: usize, align: usize) -> Self {
+        let align = if align < align_of::<FreeObject>() { align_of::<FreeObject>() } else { align };
+        let size_or_link = if size < size_of::<FreeObject>() { size_of::<FreeObject>() } else { size };
+        let object_size = align_up(size_or_link, align);
+        let header_size = align_up(size_of::<Slab>(), align);
+        let mut slab_size = PAGE_SIZE;
+        while slab_size < header_size + object_size * MIN_OBJECTS_PER_SLAB {
+            slab_size *= 2;
+        }
+        SlabCache {
+            name, align, object_size, header_size, slab_size,
+            capacity: (slab_size - header_size) / object_size,
+            partial: null_mut(),
+            full: null_mut(),
+            empty: null_mut(),
+            slabs: 0,
+            objects_in_use: 0
+        }
+    }
+
+    fn object_layout(&self) -> Layout {
+        unsafe { Layout::from_size_align_unchecked(self.object_size, self.align) }
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            name: self.name,
+            object_size: self.object_size,
+            slab_size: self.slab_size,
+            slabs: self.slabs,
+            objects_in_use: self.objects_in_use,
+            objects_total: self.slabs * self.capacity
+        }
+    }
+}
+
+pub struct SlabAllocator {
+    caches: [SlabCache; MAX_CACHES],
+    n_caches: usize,
+    page_heap: linked_list_allocator::Heap,
+    counters: Counters
+}
+
+unsafe impl Send for SlabAllocator {}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        let mut caches = [SlabCache::EMPTY; MAX_CACHES];
+        let mut idx = 0;
+        while idx < BLOCK_SIZES.len() {
+            let size = BLOCK_SIZES[idx];
+            caches[idx] = SlabCache::new(CacheName::SizeClass(size), size, size);
+            idx += 1;
+        }
+        SlabAllocator {
+            caches,
+            n_caches: BLOCK_SIZES.len(),
+            page_heap: linked_list_allocator::Heap::empty(),
+            counters: Counters::new()
+        }
+    }
+
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.page_heap.init(heap_start, heap_size);
+        self.create_cache("task", Layout::new::<Task>());
+    }
+
+    pub fn create_cache(&mut self, name: &'static str, layout: Layout) -> Option<CacheId> {
+        if self.n_caches == MAX_CACHES {
+            return None;
+        }
+        self.caches[self.n_caches] = SlabCache::new(CacheName::Named(name), layout.size(), layout.align());
+        self.n_caches += 1;
+        Some(CacheId(self.n_caches - 1))
+    }
+
+    // counted with the padded object size, that is what the object takes up
+    pub fn cache_alloc(&mut self, id: CacheId) -> *mut u8 {
+        let ptr = self.alloc_object(id.0);
+        if !ptr.is_null() {
+            let layout = self.caches[id.0].object_layout();
+            self.counters.record_alloc(layout);
+        }
+        ptr
+    }
+
+    pub unsafe fn cache_free(&mut self, id: CacheId, ptr: *mut u8) {
+        let layout = self.caches[id.0].object_layout();
+        self.counters.record_dealloc(layout);
+        self.free_object(id.0, ptr);
+    }
+
+    // hands `value` back when there is no memory for it
+    pub fn alloc_typed<T: CachedObject>(&mut self, value: T) -> Result<NonNull<T>, T> {
+        let layout = self.caches[T::CACHE.0].object_layout();
+        debug_assert!(Layout::new::<T>().size() <= layout.size() && Layout::new::<T>().align() <= layout.align());
+        let ptr = match NonNull::new(self.cache_alloc(T::CACHE) as *mut T) {
+            Some(ptr) => ptr,
+            None => return Err(value)
+        };
+        unsafe { ptr.as_ptr().write(value) };
+        Ok(ptr)
+    }
+
+    // moves the object out and frees its slot, `ptr` has to come from
+    // `alloc_typed`
+    pub unsafe fn free_typed<T: CachedObject>(&mut self, ptr: NonNull<T>) -> T {
+        let value = ptr.as_ptr().read();
+        self.cache_free(T::CACHE, ptr.as_ptr() as *mut u8);
+        value
+    }
+
+    pub fn cache_stats_of(&self, id: CacheId) -> CacheStats {
+        self.caches[id.0].stats()
+    }
+
+    // gives the slabs kept back for reuse to the page heap, returns how many
+    // bytes that were
+    pub fn release_empty_slabs(&mut self) -> usize {
+        let mut released = 0;
+        for cache in self.caches[..self.n_caches].iter_mut() {
+            if cache.empty.is_null() {
+                continue;
+            }
+            let slab = core::mem::replace(&mut cache.empty, null_mut());
+            cache.slabs -= 1;
+            released += cache.slab_size;
+            unsafe {
+                let layout = Layout::from_size_align_unchecked(cache.slab_size, cache.slab_size);
+                self.page_heap.deallocate(NonNull::new_unchecked(slab as *mut u8), layout);
+            }
+        }
+        released
+    }
+
+    pub fn cache_stats(&self) -> impl Iterator<Item = CacheStats> + '_ {
+        self.caches[..self.n_caches].iter().map(SlabCache::stats)
+    }
+
+    pub fn stats(&mut self) -> HeapStats {
+        let free = self.page_heap.free();
+        let mut stats = HeapStats::new(self.counters, free, None);
+        for (len, cache) in stats.free_list_lengths.iter_mut().zip(self.caches.iter()) {
+            *len = cache.slabs * cache.capacity - cache.objects_in_use;
+        }
+        stats
+    }
+
+    fn cache_index(&self, layout: &Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        BLOCK_SIZES.iter().position(|&x| x >= size)
+    }
+
+    fn alloc_object(&mut self, idx: usize) -> *mut u8 {
+        if self.caches[idx].partial.is_null() {
+            let empty = core::mem::replace(&mut self.caches[idx].empty, null_mut());
+            let slab = if empty.is_null() { self.new_slab(idx) } else { empty };
+            if slab.is_null() {
+                return null_mut();
+            }
+            self.caches[idx].partial = slab;
+        }
+        let cache = &mut self.caches[idx];
+        unsafe {
+            let slab = &mut *cache.partial;
+            let object = slab.free;
+            slab.free = (*object).next;
+            slab.in_use += 1;
+            if slab.free.is_null() {
+                cache.partial = slab.next;
+                slab.next = cache.full;
+                cache.full = slab;
+            }
+            cache.objects_in_use += 1;
+            object as *mut u8
+        }
+    }
+
+    fn new_slab(&mut self, idx: usize) -> *mut Slab {
+        let cache = &mut self.caches[idx];
+        let layout = Layout::from_size_align(cache.slab_size, cache.slab_size).unwrap();
+        let slab = super::heap_alloc(&mut self.page_heap, layout) as *mut Slab;
+        if slab.is_null() {
+            return null_mut();
+        }
+
+        let first_object = slab as usize + cache.header_size;
+        let mut free = null_mut();
+        for i in (0..cache.capacity).rev() {
+            let object = (first_object + i * cache.object_size) as *mut FreeObject;
+            unsafe { object.write(FreeObject { next: free }) };
+            free = object;
+        }
+        unsafe { slab.write(Slab { next: null_mut(), free, in_use: 0 }) };
+        cache.slabs += 1;
+        slab
+    }
+
+    unsafe fn free_object(&mut self, idx: usize, ptr: *mut u8) {
+        let cache = &mut self.caches[idx];
+        let slab_ptr = (ptr as usize & !(cache.slab_size - 1)) as *mut Slab;
+        let slab = &mut *slab_ptr;
+        if slab.free.is_null() {
+            unlink(&mut cache.full, slab_ptr);
+            slab.next = cache.partial;
+            cache.partial = slab_ptr;
+        }
+        let object = ptr as *mut FreeObject;
+        object.write(FreeObject { next: slab.free });
+        slab.free = object;
+        slab.in_use -= 1;
+        cache.objects_in_use -= 1;
+
+        if slab.in_use == 0 {
+            unlink(&mut cache.partial, slab_ptr);
+            if cache.empty.is_null() {
+                slab.next = null_mut();
+                cache.empty = slab_ptr;
+                return;
+            }
+            cache.slabs -= 1;
+            let layout = Layout::from_size_align_unchecked(cache.slab_size, cache.slab_size);
+            self.page_heap.deallocate(NonNull::new_unchecked(slab_ptr as *mut u8), layout);
+        }
+    }
+}
+
+unsafe fn unlink(list: &mut *mut Slab, slab: *mut Slab) {
+    let mut link = list as *mut *mut Slab;
+    while !(*link).is_null() {
+        if *link == slab {
+            *link = (*slab).next;
+            return;
+        }
+        link = &mut (**link).next;
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<SlabAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        let ptr = match allocator.cache_index(&layout) {
+            Some(idx) => allocator.alloc_object(idx),
+            None => super::heap_alloc(&mut allocator.page_heap, layout)
+        };
+        if !ptr.is_null() {
+            allocator.counters.record_alloc(layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        allocator.counters.record_dealloc(layout);
+        match allocator.cache_index(&layout) {
+            Some(idx) => allocator.free_object(idx, ptr),
+            None => allocator.page_heap.deallocate(NonNull::new(ptr).unwrap(), layout)
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        {
+            let mut allocator = self.lock();
+            let idx = allocator.cache_index(&layout);
+            if idx.is_some() && allocator.cache_index(&new_layout) == idx {
+                allocator.counters.record_realloc(layout, new_size);
+                return ptr;
+            }
+        }
+        super::realloc_by_copy(self, ptr, layout, new_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{alloc::{GlobalAlloc, Layout}, ptr::addr_of_mut};
+    use super::{BLOCK_SIZES, CacheId, CachedObject, Locked, SlabAllocator};
+
+    const ARENA_SIZE: usize = 192 * 1024;
+
+    #[repr(align(4096))]
+    #[allow(dead_code)]
+    struct Arena([u8; ARENA_SIZE]);
+
+    static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+    // the cache `init` creates for tasks comes first
+    const NODE_CACHE: CacheId = CacheId(BLOCK_SIZES.len() + 1);
+
+    struct Node([u64; 5]);
+
+    impl CachedObject for Node {
+        const CACHE: CacheId = NODE_CACHE;
+    }
+
+    #[test_case]
+    fn one_empty_slab_is_kept() {
+        unsafe {
+            let allocator = Locked::new(SlabAllocator::new());
+            allocator.lock().init(addr_of_mut!(ARENA) as usize, ARENA_SIZE);
+            let free = allocator.lock().stats().free_bytes;
+
+            let layout = Layout::new::<u64>();
+            let mut objects = [core::ptr::null_mut(); 16];
+            for object in objects.iter_mut() {
+                *object = allocator.alloc(layout);
+                assert!(!object.is_null());
+            }
+            let size_8 = allocator.lock().cache_stats().next().unwrap();
+            assert_eq!((size_8.slabs, size_8.objects_in_use), (1, 16));
+
+            for &object in objects.iter() {
+                allocator.dealloc(object, layout);
+            }
+            let size_8 = allocator.lock().cache_stats().next().unwrap();
+            assert_eq!((size_8.slabs, size_8.objects_in_use), (1, 0));
+            assert_eq!(allocator.lock().stats().free_bytes, free - size_8.slab_size);
+
+            // the kept slab is reused instead of carving a new one
+            let object = allocator.alloc(layout);
+            assert_eq!(allocator.lock().stats().free_bytes, free - size_8.slab_size);
+            allocator.dealloc(object, layout);
+
+            assert_eq!(allocator.lock().release_empty_slabs(), size_8.slab_size);
+            assert_eq!(allocator.lock().cache_stats().next().unwrap().slabs, 0);
+            assert_eq!(allocator.lock().stats().free_bytes, free);
+        }
+    }
+
+    #[test_case]
+    fn named_caches_only_take_typed_allocations() {
+        unsafe {
+            let allocator = Locked::new(SlabAllocator::new());
+            allocator.lock().init(addr_of_mut!(ARENA) as usize, ARENA_SIZE);
+            let id = allocator.lock().create_cache("node", Layout::new::<Node>()).unwrap();
+            assert_eq!(id, NODE_CACHE);
+
+            // same size, but a plain request goes to a size class
+            let plain = allocator.alloc(Layout::new::<Node>());
+            assert_eq!(allocator.lock().cache_stats_of(id).objects_in_use, 0);
+            allocator.dealloc(plain, Layout::new::<Node>());
+
+            let before = allocator.lock().stats().counters;
+            let node = allocator.lock().alloc_typed(Node([7; 5])).ok().unwrap();
+            assert_eq!(allocator.lock().cache_stats_of(id).objects_in_use, 1);
+            let raw = allocator.lock().cache_alloc(id);
+            assert_eq!(allocator.lock().stats().counters.allocations, before.allocations + 2);
+
+            allocator.lock().cache_free(id, raw);
+            assert_eq!(allocator.lock().free_typed(node).0, [7; 5]);
+            let after = allocator.lock().stats().counters;
+            assert_eq!(after.frees, before.frees + 2);
+            assert_eq!(after.bytes_in_use, before.bytes_in_use);
+            assert_eq!(allocator.lock().cache_stats_of(id).objects_in_use, 0);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use core::{alloc::Layout, fmt};
+
+use super::fixed_size_block::BLOCK_SIZES;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Counters {
+    pub bytes_in_use: usize,
+    pub peak_bytes_in_use: usize,
+    pub allocations: usize,
+    pub frees: usize
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Counters { bytes_in_use: 0, peak_bytes_in_use: 0, allocations: 0, frees: 0 }
+    }
+
+    pub fn record_alloc(&mut self, layout: Layout) {
+        self.bytes_in_use += layout.size();
+        self.peak_bytes_in_use = self.peak_bytes_in_use.max(self.bytes_in_use);
+        self.allocations += 1;
+    }
+
+    pub fn record_realloc(&mut self, layout: Layout, new_size: usize) {
+        self.bytes_in_use = self.bytes_in_use - layout.size() + new_size;
+        self.peak_bytes_in_use = self.peak_bytes_in_use.max(self.bytes_in_use);
+    }
+
+    pub fn record_dealloc(&mut self, layout: Layout) {
+        self.bytes_in_use -= layout.size();
+        self.frees += 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub counters: Counters,
+    pub free_list_lengths: [usize; BLOCK_SIZES.len()],
+    pub free_bytes: usize,
+    // None for heaps backed by linked_list_allocator, which keeps its hole
+    // list to itself
+    pub largest_free_region: Option<usize>,
+    pub committed: usize
+}
+
+impl HeapStats {
+    pub fn new(counters: Counters, free_bytes: usize, largest_free_region: Option<usize>) -> Self {
+        HeapStats {
+            counters,
+            free_list_lengths: [0; BLOCK_SIZES.len()],
+            free_bytes,
+            largest_free_region,
+            committed: 0
+        }
+    }
+
+    // share of free memory that is not usable for one big allocation
+    pub fn fragmentation_percent(&self) -> Option<usize> {
+        let largest = self.largest_free_region?;
+        if self.free_bytes == 0 {
+            Some(0)
+        } else {
+            Some(100 - largest * 100 / self.free_bytes)
+        }
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counters = &self.counters;
+        writeln!(f, "heap: {} bytes in use (peak {}), {} bytes committed",
+            counters.bytes_in_use, counters.peak_bytes_in_use, self.committed)?;
+        writeln!(f, "      {} allocations, {} frees", counters.allocations, counters.frees)?;
+        write!(f, "      free lists:")?;
+        for (size, len) in BLOCK_SIZES.iter().zip(self.free_list_lengths.iter()) {
+            write!(f, " {}:{}", size, len)?;
+        }
+        writeln!(f)?;
+        write!(f, "      {} bytes free", self.free_bytes)?;
+        match (self.largest_free_region, self.fragmentation_percent()) {
+            (Some(largest), Some(percent)) =>
+                write!(f, ", largest free region {} bytes ({}% fragmented)", largest, percent),
+            _ => write!(f, ", largest free region unknown")
+        }
+    }
+}
@@ -0,0 +1,133 @@
+pub mod io_apic;
+pub mod local;
+
+use conquer_once::spin::OnceCell;
+use x86_64::{PhysAddr, VirtAddr, instructions::{interrupts, port::Port}, structures::paging::{Size4KiB, mapper::MapToError}};
+
+use crate::{acpi::{self, AcpiError, IrqRoute}, cpu, memory::{self, KernelMemory, paging, regions}};
+
+use self::{io_apic::IoApic, local::LocalApic};
+
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+const MAX_IO_APICS: usize = 8;
+const REGISTERS_SIZE: u64 = 4096;
+
+const PIC_1_DATA: u16 = 0x21;
+const PIC_2_DATA: u16 = 0xa1;
+
+const ISA_IRQS: u8 = 16;
+
+#[derive(Debug)]
+pub enum ApicError {
+    NoLocalApic,
+    NoKernelMemory,
+    Acpi(AcpiError),
+    NoIoApic,
+    // the firmware put registers into RAM, better not touch them
+    NotMmio(PhysAddr),
+    Map(MapToError<Size4KiB>)
+}
+
+impl From<AcpiError> for ApicError {
+    fn from(err: AcpiError) -> Self {
+        ApicError::Acpi(err)
+    }
+}
+
+impl From<MapToError<Size4KiB>> for ApicError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        ApicError::Map(err)
+    }
+}
+
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+const NO_IO_APIC: Option<IoApic> = None;
+static IO_APICS: spin::Mutex<[Option<IoApic>; MAX_IO_APICS]> = spin::Mutex::new([NO_IO_APIC; MAX_IO_APICS]);
+static MADT: OnceCell<acpi::Madt> = OnceCell::uninit();
+
+// needs the kernel memory, until this succeeds the 8259 stays in charge
+pub fn init() -> Result<(), ApicError> {
+    if !cpu::features().apic {
+        return Err(ApicError::NoLocalApic);
+    }
+    let mut memory = memory::kernel_memory().ok_or(ApicError::NoKernelMemory)?;
+    let madt = unsafe { acpi::find_madt(memory.mapper.phys_offset())? };
+    if madt.io_apics().next().is_none() {
+        return Err(ApicError::NoIoApic);
+    }
+
+    let local_apic = unsafe { LocalApic::new(map_registers(&mut memory, LocalApic::base_address())?) };
+    let mut io_apics = IO_APICS.lock();
+    for (slot, entry) in io_apics.iter_mut().zip(madt.io_apics()) {
+        let base = map_registers(&mut memory, entry.address)?;
+        let mut io_apic = unsafe { IoApic::new(base, entry.gsi_base) };
+        io_apic.mask_all();
+        *slot = Some(io_apic);
+    }
+    drop(io_apics);
+    drop(memory);
+
+    interrupts::without_interrupts(|| {
+        disable_pic();
+        unsafe { local_apic.enable(SPURIOUS_VECTOR) };
+        LOCAL_APIC.init_once(|| local_apic);
+        MADT.init_once(|| madt);
+        crate::interrupts::irq::route_registered();
+    });
+    Ok(())
+}
+
+// delivers `irq` as `vector` to this CPU, false if no I/O APIC takes it;
+// lines below 16 are ISA IRQs, the ones above PCI style GSIs
+pub fn route_irq(irq: u8, vector: u8) -> bool {
+    let (local_apic, madt) = match (LOCAL_APIC.try_get(), MADT.try_get()) {
+        (Ok(local_apic), Ok(madt)) => (local_apic, madt),
+        _ => return false
+    };
+    let route = irq_route(madt, irq);
+    match IO_APICS.lock().iter_mut().flatten().find(|io_apic| io_apic.handles(route.gsi)) {
+        Some(io_apic) => {
+            io_apic.route(route, vector, local_apic.id());
+            true
+        }
+        None => false
+    }
+}
+
+pub fn mask_irq(irq: u8) {
+    if let Ok(madt) = MADT.try_get() {
+        let gsi = irq_route(madt, irq).gsi;
+        if let Some(io_apic) = IO_APICS.lock().iter_mut().flatten().find(|io_apic| io_apic.handles(gsi)) {
+            io_apic.set_masked(gsi, true);
+        }
+    }
+}
+
+fn irq_route(madt: &acpi::Madt, irq: u8) -> IrqRoute {
+    if irq < ISA_IRQS {
+        madt.isa_route(irq)
+    } else {
+        IrqRoute { gsi: u32::from(irq), active_low: true, level_triggered: true }
+    }
+}
+
+fn map_registers(memory: &mut KernelMemory, addr: PhysAddr) -> Result<VirtAddr, ApicError> {
+    if regions::is_ram(addr) {
+        return Err(ApicError::NotMmio(addr));
+    }
+    let KernelMemory { mapper, frame_allocator } = memory;
+    Ok(paging::identity_map_mmio(addr, REGISTERS_SIZE, mapper, frame_allocator)?)
+}
+
+// remapped by `crate::init` already, masking every line keeps stray
+// interrupts away from the exception vectors
+fn disable_pic() {
+    unsafe {
+        Port::<u8>::new(PIC_1_DATA).write(0xff);
+        Port::<u8>::new(PIC_2_DATA).write(0xff);
+    }
+}
+
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.try_get().ok()
+}
@@ -0,0 +1,84 @@
+use core::ptr;
+
+use x86_64::VirtAddr;
+
+use crate::acpi::IrqRoute;
+
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+
+const IOAPICVER: u32 = 0x01;
+const IOREDTBL: u32 = 0x10;
+
+const ACTIVE_LOW: u64 = 1 << 13;
+const LEVEL_TRIGGERED: u64 = 1 << 15;
+const MASKED: u64 = 1 << 16;
+
+// the register window is a select/data pair, callers have to serialize
+// accesses to one I/O APIC
+pub struct IoApic {
+    base: VirtAddr,
+    gsi_base: u32,
+    entries: u32
+}
+
+impl IoApic {
+    // `base` has to map the registers uncached
+    pub unsafe fn new(base: VirtAddr, gsi_base: u32) -> Self {
+        let mut io_apic = IoApic { base, gsi_base, entries: 0 };
+        io_apic.entries = ((io_apic.read(IOAPICVER) >> 16) & 0xff) + 1;
+        io_apic
+    }
+
+    pub fn handles(&self, gsi: u32) -> bool {
+        self.gsi_base <= gsi && gsi < self.gsi_base + self.entries
+    }
+
+    pub fn mask_all(&mut self) {
+        for idx in 0..self.entries {
+            unsafe { self.write_entry(idx, MASKED) };
+        }
+    }
+
+    // delivers the interrupt as `vector` to the local APIC with `apic_id`,
+    // fixed delivery in physical destination mode
+    pub fn route(&mut self, route: IrqRoute, vector: u8, apic_id: u8) {
+        assert!(self.handles(route.gsi));
+        let mut entry = u64::from(vector) | u64::from(apic_id) << 56;
+        if route.active_low {
+            entry |= ACTIVE_LOW;
+        }
+        if route.level_triggered {
+            entry |= LEVEL_TRIGGERED;
+        }
+        unsafe { self.write_entry(route.gsi - self.gsi_base, entry) };
+    }
+
+    pub fn set_masked(&mut self, gsi: u32, masked: bool) {
+        assert!(self.handles(gsi));
+        let reg = IOREDTBL + 2 * (gsi - self.gsi_base);
+        unsafe {
+            let low = self.read(reg);
+            let low = if masked { low | MASKED as u32 } else { low & !(MASKED as u32) };
+            self.write(reg, low);
+        }
+    }
+
+    unsafe fn write_entry(&mut self, idx: u32, entry: u64) {
+        let reg = IOREDTBL + 2 * idx;
+        // masked while the halves do not match yet
+        self.write(reg, MASKED as u32);
+        self.write(reg + 1, (entry >> 32) as u32);
+        self.write(reg, entry as u32);
+    }
+
+    unsafe fn read(&self, reg: u32) -> u32 {
+        ptr::write_volatile((self.base + IOREGSEL).as_mut_ptr(), reg);
+        ptr::read_volatile((self.base + IOWIN).as_ptr())
+    }
+
+    unsafe fn write(&mut self, reg: u32, value: u32) {
+        ptr::write_volatile((self.base + IOREGSEL).as_mut_ptr(), reg);
+        ptr::write_volatile((self.base + IOWIN).as_mut_ptr(), value);
+    }
+}
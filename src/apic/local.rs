@@ -0,0 +1,62 @@
+use core::ptr;
+
+use x86_64::{PhysAddr, VirtAddr, registers::model_specific::Msr};
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+const ID: usize = 0x20;
+const TASK_PRIORITY: usize = 0x80;
+const EOI: usize = 0xb0;
+const SPURIOUS_VECTOR: usize = 0xf0;
+const LVT_TIMER: usize = 0x320;
+const LVT_LINT0: usize = 0x350;
+const LVT_ERROR: usize = 0x370;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+
+pub struct LocalApic {
+    base: VirtAddr
+}
+
+impl LocalApic {
+    // where the MSR says the registers are, the MADT may only repeat that
+    pub fn base_address() -> PhysAddr {
+        PhysAddr::new(unsafe { Msr::new(IA32_APIC_BASE).read() } & APIC_BASE_MASK)
+    }
+
+    // `base` has to map the registers uncached
+    pub unsafe fn new(base: VirtAddr) -> Self {
+        LocalApic { base }
+    }
+
+    pub unsafe fn enable(&self, spurious_vector: u8) {
+        let mut msr = Msr::new(IA32_APIC_BASE);
+        let value = msr.read();
+        msr.write(value | APIC_BASE_ENABLE);
+        self.write(TASK_PRIORITY, 0);
+        // the 8259 is masked, so the virtual wire on LINT0 carries nothing
+        self.write(LVT_LINT0, LVT_MASKED);
+        self.write(LVT_TIMER, LVT_MASKED);
+        self.write(LVT_ERROR, LVT_MASKED);
+        self.write(SPURIOUS_VECTOR, SOFTWARE_ENABLE | u32::from(spurious_vector));
+    }
+
+    pub fn id(&self) -> u8 {
+        (unsafe { self.read(ID) } >> 24) as u8
+    }
+
+    pub fn end_of_interrupt(&self) {
+        unsafe { self.write(EOI, 0) };
+    }
+
+    unsafe fn read(&self, reg: usize) -> u32 {
+        ptr::read_volatile((self.base + reg).as_ptr())
+    }
+
+    unsafe fn write(&self, reg: usize, value: u32) {
+        ptr::write_volatile((self.base + reg).as_mut_ptr(), value);
+    }
+}
@@ -0,0 +1,52 @@
+use core::arch::asm;
+
+use x86_64::VirtAddr;
+
+// the saved rbp and the return address pushed in front of it
+#[repr(C)]
+struct Frame {
+    rbp: *const Frame,
+    return_addr: usize
+}
+
+// walks the rbp chain of the caller, so the kernel has to be built with
+// frame pointers (see .cargo/config.toml); returns how many of `out` were filled
+#[inline(always)]
+pub fn return_addresses(out: &mut [usize]) -> usize {
+    let rbp: usize;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    unsafe { walk(rbp, out) }
+}
+
+// `rbp` has to be a frame pointer of the current stack
+pub unsafe fn walk(rbp: usize, out: &mut [usize]) -> usize {
+    walk_checked(rbp, out, |_| true)
+}
+
+// for chains that may be corrupted, such as the one of a faulting context:
+// a frame is only read once `is_mapped` accepts both of its words
+pub unsafe fn walk_checked(rbp: usize, out: &mut [usize], is_mapped: impl Fn(VirtAddr) -> bool) -> usize {
+    let mut frame = rbp as *const Frame;
+    let mut depth = 0;
+    while depth < out.len() && is_plausible(frame)
+        && is_mapped(VirtAddr::new(frame as u64))
+        && is_mapped(VirtAddr::new(frame as u64 + 8)) {
+        let Frame { rbp, return_addr } = frame.read();
+        if return_addr == 0 {
+            break;
+        }
+        out[depth] = return_addr;
+        depth += 1;
+        // stacks grow down, so the caller's frame always lies above
+        if (rbp as usize) <= frame as usize {
+            break;
+        }
+        frame = rbp;
+    }
+    depth
+}
+
+fn is_plausible(frame: *const Frame) -> bool {
+    let addr = frame as usize;
+    addr != 0 && addr % 8 == 0 && VirtAddr::try_new(addr as u64).is_ok()
+}
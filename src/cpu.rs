@@ -0,0 +1,41 @@
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+use x86_64::registers::{control::{Cr0, Cr0Flags, Cr4, Cr4Flags}, model_specific::{Efer, EferFlags}};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Features {
+    pub nx: bool,
+    pub apic: bool,
+    pub smep: bool,
+    pub smap: bool
+}
+
+pub fn features() -> Features {
+    let max_leaf = __cpuid(0).eax;
+    let max_extended_leaf = __cpuid(0x8000_0000).eax;
+    let apic = __cpuid(1).edx & (1 << 9) != 0;
+    let (smep, smap) = if max_leaf >= 7 {
+        let ebx = __cpuid_count(7, 0).ebx;
+        (ebx & (1 << 7) != 0, ebx & (1 << 20) != 0)
+    } else {
+        (false, false)
+    };
+    let nx = max_extended_leaf >= 0x8000_0001
+        && __cpuid(0x8000_0001).edx & (1 << 20) != 0;
+    Features { nx, apic, smep, smap }
+}
+
+// has to run before anything maps pages with NO_EXECUTE
+pub fn init() {
+    let features = features();
+    assert!(features.nx, "the CPU does not support no-execute pages");
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        // read-only pages are read-only for the kernel too
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+        Cr4::update(|flags| {
+            flags.set(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION, features.smep);
+            flags.set(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION, features.smap);
+        });
+    }
+}
@@ -1,3 +1,5 @@
+use core::{cell::UnsafeCell, ptr::{addr_of, addr_of_mut}};
+
 use lazy_static::lazy_static;
 use x86_64::instructions::tables::load_tss;
 use x86_64::registers::segmentation::CS;
@@ -6,24 +8,46 @@ use x86_64::structures::tss;
 use x86_64::structures::gdt;
 use x86_64::VirtAddr;
 
+use crate::memory::stack::{KernelStack, StackError};
+
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+// #PF stays on the current stack, a fault inside its handler would overwrite
+// an IST stack; an overflow into a guard page ends up as a double fault
+const IST_STACKS: [(u16, &str); 3] = [
+    (DOUBLE_FAULT_IST_INDEX, "double fault stack"),
+    (NMI_IST_INDEX, "NMI stack"),
+    (MACHINE_CHECK_IST_INDEX, "machine check stack")
+];
+const IST_STACK_PAGES: u64 = 5;
+
+// the CPU reads the IST entries on every interrupt and `init_stacks` swaps
+// them once, so there is no lock around the TSS, only this cell
+struct Tss(UnsafeCell<tss::TaskStateSegment>);
+
+unsafe impl Sync for Tss {}
 
 lazy_static! {
-    static ref TSS: tss::TaskStateSegment = {
+    static ref TSS: Tss = {
+        // used until `init_stacks` moves the handlers to guarded stacks
+        const STACK_SIZE: usize = 4096 * 5;
+        static mut STACKS: [[u8; STACK_SIZE]; IST_STACKS.len()] = [[0; STACK_SIZE]; IST_STACKS.len()];
         let mut tss = tss::TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(unsafe{ &STACK });
-            stack_start + STACK_SIZE
-        };
-        tss
+        for (stack, &(index, _)) in IST_STACKS.iter().enumerate() {
+            let stack_start = VirtAddr::from_ptr(unsafe { addr_of!(STACKS[stack]) });
+            tss.interrupt_stack_table[index as usize] = stack_start + STACK_SIZE;
+        }
+        Tss(UnsafeCell::new(tss))
     };
+}
 
+lazy_static! {
     static ref GDT: (gdt::GlobalDescriptorTable, Selectors) = {
         let mut gdt = gdt::GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(gdt::Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(gdt::Descriptor::tss_segment(&TSS));
+        let tss_selector = gdt.add_entry(gdt::Descriptor::tss_segment(unsafe { &*TSS.0.get() }));
         (gdt, Selectors{code_selector, tss_selector})
     };
 }
@@ -41,3 +65,15 @@ pub fn init() {
         load_tss(GDT.1.tss_selector);
     }
 }
+
+// needs the kernel memory, the stacks stay in use as long as the kernel runs
+pub fn init_stacks() -> Result<(), StackError> {
+    for &(index, name) in IST_STACKS.iter() {
+        let stack = KernelStack::allocate(name, IST_STACK_PAGES)?;
+        let entry = unsafe { addr_of_mut!((*TSS.0.get()).interrupt_stack_table[index as usize]) };
+        // a single aligned store, an interrupt sees either the old or the new stack
+        unsafe { entry.write_volatile(stack.top()) };
+        core::mem::forget(stack);
+    }
+    Ok(())
+}
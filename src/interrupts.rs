@@ -1,29 +1,33 @@
+pub mod exceptions;
+pub mod irq;
+pub mod trap;
+
 use pc_keyboard::{Keyboard, layouts::Us104Key, ScancodeSet1, HandleControl};
-use x86_64::{structures::idt::{self, PageFaultErrorCode}, instructions::port::Port, registers::control::Cr2};
-use crate::{print, println, gdt, hlt_loop};
+use x86_64::{structures::idt, instructions::port::Port};
+use crate::{apic, time};
 use lazy_static::lazy_static;
 use spin;
 use pic8259;
 
+use self::irq::IrqStatus;
+
 lazy_static! {
     static ref IDT: idt::InterruptDescriptorTable = {
         let mut idt = idt::InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        unsafe {
-            idt.double_fault.set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-        }
-        idt[InterruptIndex::Timer.as_usize()]
-            .set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_usize()]
-            .set_handler_fn(keyboard_interrupt_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        exceptions::install(&mut idt);
+        irq::install_trampolines(&mut idt);
+        idt[usize::from(apic::SPURIOUS_VECTOR)]
+            .set_handler_fn(spurious_interrupt_handler);
         idt
     };
 }
 
 pub fn init_idt() {
     IDT.load();
+    irq::register(InterruptIndex::Timer.irq(), "timer", timer_interrupt)
+        .expect("timer irq is taken");
+    irq::register(InterruptIndex::Keyboard.irq(), "keyboard", keyboard_interrupt)
+        .expect("keyboard irq is taken");
 }
 
 pub const PIC_1_OFFSET: u8 = 32;
@@ -41,40 +45,26 @@ pub enum InterruptIndex {
 }
 
 impl InterruptIndex {
-    fn as_u8(self) -> u8 {
+    pub fn as_u8(self) -> u8 {
         self as u8
     }
 
-    fn as_usize(self) -> usize {
-        usize::from(self.as_u8())
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
     }
 }
 
-extern "x86-interrupt" fn breakpoint_handler(
-    stack_frame: idt::InterruptStackFrame
-) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+fn timer_interrupt(_irq: u8) -> IrqStatus {
+    time::tick();
+    IrqStatus::Handled
 }
 
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: idt::InterruptStackFrame,
-    _error_code: u64
-) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
-}
-
-extern "x86-interrupt" fn timer_interrupt_handler(
+// the local APIC does not expect an EOI for these
+extern "x86-interrupt" fn spurious_interrupt_handler(
     _stack_frame: idt::InterruptStackFrame
-) {
-    print!(".");
-    unsafe {
-        PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
-}
+) {}
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(
-    _stack_frame: idt::InterruptStackFrame
-) {
+fn keyboard_interrupt(_irq: u8) -> IrqStatus {
     lazy_static! {
         static ref KEYBOARD: spin::Mutex<Keyboard<Us104Key, ScancodeSet1>> = 
             spin::Mutex::new(Keyboard::new(Us104Key, ScancodeSet1, 
@@ -107,18 +97,5 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
     //     }
     // }
 
-    unsafe {
-        PICS.lock().notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
-}
-
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: idt::InterruptStackFrame,
-    page_fault_error_code: PageFaultErrorCode
-) {
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", page_fault_error_code);
-    println!("{:#?}", stack_frame);
-    hlt_loop();
+    IrqStatus::Handled
 }
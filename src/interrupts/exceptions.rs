@@ -0,0 +1,297 @@
+use core::fmt::{self, Write};
+#[cfg(test)]
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::{VirtAddr, registers::{control::{Cr0, Cr2, Cr3, Cr4}, model_specific::Efer}, structures::idt::{InterruptDescriptorTable, PageFaultErrorCode}};
+
+use crate::{backtrace, gdt, memory::{self, guard}, println, vga_buffer::WRITER};
+
+use super::trap::{self, TrapFrame};
+
+const BACKTRACE_LEN: usize = 16;
+
+const DEBUG: u64 = 1;
+const NMI: u64 = 2;
+const BREAKPOINT: u64 = 3;
+const DOUBLE_FAULT: u64 = 8;
+const PAGE_FAULT: u64 = 14;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorCode {
+    None,
+    // #TS, #NP, #SS and #GP name the segment selector that caused them
+    Selector(u64),
+    Page(PageFaultErrorCode),
+    ControlProtection(u64),
+    Raw(u64)
+}
+
+impl ErrorCode {
+    fn decode(vector: u64, code: u64) -> ErrorCode {
+        match vector {
+            10..=13 => ErrorCode::Selector(code),
+            PAGE_FAULT => ErrorCode::Page(PageFaultErrorCode::from_bits_truncate(code)),
+            21 => ErrorCode::ControlProtection(code),
+            DOUBLE_FAULT | 17 | 29 | 30 => ErrorCode::Raw(code),
+            _ => ErrorCode::None
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ErrorCode::None => write!(f, "none"),
+            ErrorCode::Selector(0) => write!(f, "0x0 (no selector)"),
+            ErrorCode::Selector(code) => {
+                let table = match (code >> 1) & 0b11 {
+                    0b00 => "GDT",
+                    0b10 => "LDT",
+                    _ => "IDT"
+                };
+                write!(f, "{:#x} (index {} in the {}{})", code, code >> 3, table,
+                    if code & 1 != 0 { ", external event" } else { "" })
+            }
+            ErrorCode::Page(code) => write!(f, "{:?}", code),
+            ErrorCode::ControlProtection(code) => {
+                let cause = match code & 0x7fff {
+                    1 => "near return",
+                    2 => "far return or iret",
+                    3 => "missing endbranch",
+                    4 => "rstorssp",
+                    5 => "setssbsy",
+                    _ => "unknown"
+                };
+                write!(f, "{:#x} ({})", code, cause)
+            }
+            ErrorCode::Raw(code) => write!(f, "{:#x}", code)
+        }
+    }
+}
+
+fn describe(vector: u64) -> (&'static str, &'static str) {
+    match vector {
+        0 => ("DIVIDE ERROR", "#DE"),
+        DEBUG => ("DEBUG", "#DB"),
+        NMI => ("NMI", "NMI"),
+        BREAKPOINT => ("BREAKPOINT", "#BP"),
+        4 => ("OVERFLOW", "#OF"),
+        5 => ("BOUND RANGE EXCEEDED", "#BR"),
+        6 => ("INVALID OPCODE", "#UD"),
+        7 => ("DEVICE NOT AVAILABLE", "#NM"),
+        DOUBLE_FAULT => ("DOUBLE FAULT", "#DF"),
+        10 => ("INVALID TSS", "#TS"),
+        11 => ("SEGMENT NOT PRESENT", "#NP"),
+        12 => ("STACK SEGMENT FAULT", "#SS"),
+        13 => ("GENERAL PROTECTION FAULT", "#GP"),
+        PAGE_FAULT => ("PAGE FAULT", "#PF"),
+        16 => ("X87 FLOATING POINT", "#MF"),
+        17 => ("ALIGNMENT CHECK", "#AC"),
+        18 => ("MACHINE CHECK", "#MC"),
+        19 => ("SIMD FLOATING POINT", "#XM"),
+        20 => ("VIRTUALIZATION", "#VE"),
+        21 => ("CONTROL PROTECTION", "#CP"),
+        28 => ("HYPERVISOR INJECTION", "#HV"),
+        29 => ("VMM COMMUNICATION", "#VC"),
+        30 => ("SECURITY EXCEPTION", "#SX"),
+        _ => ("RESERVED", "-")
+    }
+}
+
+pub struct ExceptionReport<'a> {
+    pub frame: &'a TrapFrame
+}
+
+impl fmt::Display for ExceptionReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frame = self.frame;
+        let (name, mnemonic) = describe(frame.vector);
+        writeln!(f, "EXCEPTION: {} ({}, vector {})", name, mnemonic, frame.vector)?;
+        writeln!(f, "error code: {}", ErrorCode::decode(frame.vector, frame.error_code))?;
+        writeln!(f, "{}", frame)?;
+        writeln!(f, "cr0 {:08x} cr2 {:016x} cr3 {:016x} cr4 {:08x} efer {:x}",
+            Cr0::read_raw(), Cr2::read().as_u64(), Cr3::read().0.start_address().as_u64(),
+            Cr4::read_raw(), Efer::read_raw())?;
+        // the saved rbp leads through the callers of the interrupted code; it
+        // may be garbage, and faulting on it here would clobber this frame
+        let mut callers = [0; BACKTRACE_LEN];
+        let depth = unsafe { backtrace::walk_checked(frame.rbp as usize, &mut callers, memory::is_mapped) };
+        write!(f, "backtrace:\n  {:#018x}", frame.rip)?;
+        for addr in callers[..depth].iter() {
+            write!(f, "\n  {:#018x}", addr)?;
+        }
+        Ok(())
+    }
+}
+
+// called by the entry stubs for every exception, returning resumes at
+// `frame.rip`
+pub(super) extern "C" fn trap_dispatch(frame: &mut TrapFrame) {
+    match frame.vector {
+        // these two can hit code that is printing, the report is dropped then
+        DEBUG | NMI => {
+            if let Some(mut writer) = WRITER.try_lock() {
+                let _ = writeln!(writer, "{}", ExceptionReport { frame });
+            }
+        }
+        BREAKPOINT => {
+            println!("{}", ExceptionReport { frame });
+            #[cfg(test)]
+            {
+                if INVERT_ON_BREAKPOINT.load(Ordering::Relaxed) {
+                    invert_registers(frame);
+                }
+            }
+        }
+        PAGE_FAULT => page_fault(frame),
+        DOUBLE_FAULT => {
+            // a stack overflow faults again while pushing the page fault frame
+            if let Some(guard) = guard::find_guard(Cr2::read()) {
+                println!("overflow into the guard page of the {} at {:?}", guard.name, Cr2::read());
+            }
+            panic!("{}", ExceptionReport { frame });
+        }
+        // the kernel cannot recover from the rest, the report goes into the panic
+        _ => panic!("{}", ExceptionReport { frame })
+    }
+}
+
+fn page_fault(frame: &mut TrapFrame) {
+    let error_code = PageFaultErrorCode::from_bits_truncate(frame.error_code);
+    if memory::demand::handle_page_fault(Cr2::read(), error_code) {
+        return;
+    }
+    if let Some(guard) = guard::find_guard(Cr2::read()) {
+        println!("overflow into the guard page of the {}", guard.name);
+    } else if let Some(vma) = memory::vma::find(Cr2::read()) {
+        println!("inside {} ({:?})", vma.name, vma.kind);
+    }
+    panic!("{}", ExceptionReport { frame });
+}
+
+fn stub_addr(stub: extern "C" fn()) -> VirtAddr {
+    VirtAddr::new(stub as usize as u64)
+}
+
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    unsafe {
+        idt.divide_error.set_handler_addr(stub_addr(trap::divide_error));
+        idt.debug.set_handler_addr(stub_addr(trap::debug));
+        idt.non_maskable_interrupt.set_handler_addr(stub_addr(trap::non_maskable_interrupt))
+            .set_stack_index(gdt::NMI_IST_INDEX);
+        idt.breakpoint.set_handler_addr(stub_addr(trap::breakpoint));
+        idt.overflow.set_handler_addr(stub_addr(trap::overflow));
+        idt.bound_range_exceeded.set_handler_addr(stub_addr(trap::bound_range_exceeded));
+        idt.invalid_opcode.set_handler_addr(stub_addr(trap::invalid_opcode));
+        idt.device_not_available.set_handler_addr(stub_addr(trap::device_not_available));
+        idt.double_fault.set_handler_addr(stub_addr(trap::double_fault))
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.invalid_tss.set_handler_addr(stub_addr(trap::invalid_tss));
+        idt.segment_not_present.set_handler_addr(stub_addr(trap::segment_not_present));
+        idt.stack_segment_fault.set_handler_addr(stub_addr(trap::stack_segment_fault));
+        idt.general_protection_fault.set_handler_addr(stub_addr(trap::general_protection_fault));
+        idt.page_fault.set_handler_addr(stub_addr(trap::page_fault));
+        idt.x87_floating_point.set_handler_addr(stub_addr(trap::x87_floating_point));
+        idt.alignment_check.set_handler_addr(stub_addr(trap::alignment_check));
+        idt.machine_check.set_handler_addr(stub_addr(trap::machine_check))
+            .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+        idt.simd_floating_point.set_handler_addr(stub_addr(trap::simd_floating_point));
+        idt.virtualization.set_handler_addr(stub_addr(trap::virtualization));
+        idt.cp_protection_exception.set_handler_addr(stub_addr(trap::cp_protection_exception));
+        idt.hv_injection_exception.set_handler_addr(stub_addr(trap::hv_injection_exception));
+        idt.vmm_communication_exception.set_handler_addr(stub_addr(trap::vmm_communication_exception));
+        idt.security_exception.set_handler_addr(stub_addr(trap::security_exception));
+    }
+}
+
+// lets a test check that changes to the frame reach the interrupted code
+#[cfg(test)]
+static INVERT_ON_BREAKPOINT: AtomicBool = AtomicBool::new(false);
+
+#[cfg(test)]
+fn invert_registers(frame: &mut TrapFrame) {
+    let regs = [
+        &mut frame.rax, &mut frame.rbx, &mut frame.rcx, &mut frame.rdx, &mut frame.rsi,
+        &mut frame.rdi, &mut frame.rbp, &mut frame.r8, &mut frame.r9, &mut frame.r10,
+        &mut frame.r11, &mut frame.r12, &mut frame.r13, &mut frame.r14, &mut frame.r15
+    ];
+    for reg in regs {
+        *reg = !*reg;
+    }
+}
+
+// loads `regs` into rax, rbx, rcx, rdx, rsi, rdi, rbp and r8 to r15 in that
+// order, hits a breakpoint and stores the registers back
+#[cfg(test)]
+unsafe fn breakpoint_with(regs: &mut [u64; 15]) {
+    core::arch::asm!(
+        "push rbx",
+        "push rbp",
+        "push rdi",
+        "mov rax, [rdi]",
+        "mov rbx, [rdi + 8]",
+        "mov rcx, [rdi + 16]",
+        "mov rdx, [rdi + 24]",
+        "mov rsi, [rdi + 32]",
+        "mov rbp, [rdi + 48]",
+        "mov r8, [rdi + 56]",
+        "mov r9, [rdi + 64]",
+        "mov r10, [rdi + 72]",
+        "mov r11, [rdi + 80]",
+        "mov r12, [rdi + 88]",
+        "mov r13, [rdi + 96]",
+        "mov r14, [rdi + 104]",
+        "mov r15, [rdi + 112]",
+        "mov rdi, [rdi + 40]",
+        "int3",
+        // the pointer to `regs` is still on the stack
+        "push rdi",
+        "mov rdi, [rsp + 8]",
+        "mov [rdi], rax",
+        "mov [rdi + 8], rbx",
+        "mov [rdi + 16], rcx",
+        "mov [rdi + 24], rdx",
+        "mov [rdi + 32], rsi",
+        "mov [rdi + 48], rbp",
+        "mov [rdi + 56], r8",
+        "mov [rdi + 64], r9",
+        "mov [rdi + 72], r10",
+        "mov [rdi + 80], r11",
+        "mov [rdi + 88], r12",
+        "mov [rdi + 96], r13",
+        "mov [rdi + 104], r14",
+        "mov [rdi + 112], r15",
+        "pop qword ptr [rdi + 40]",
+        "add rsp, 8",
+        "pop rbp",
+        "pop rbx",
+        inout("rdi") regs.as_mut_ptr() => _,
+        out("rax") _, out("rcx") _, out("rdx") _, out("rsi") _,
+        out("r8") _, out("r9") _, out("r10") _, out("r11") _,
+        out("r12") _, out("r13") _, out("r14") _, out("r15") _
+    );
+}
+
+#[cfg(test)]
+const TEST_VALUES: [u64; 15] = [
+    0x1111, 0x2222, 0x3333, 0x4444, 0x5555, 0x6666, 0x7777, 0x8888,
+    0x9999, 0xaaaa, 0xbbbb, 0xcccc, 0xdddd, 0xeeee, 0xffff
+];
+
+#[test_case]
+fn registers_survive_a_breakpoint() {
+    let mut regs = TEST_VALUES;
+    unsafe { breakpoint_with(&mut regs) };
+    assert_eq!(regs, TEST_VALUES);
+}
+
+#[test_case]
+fn frame_changes_reach_the_interrupted_code() {
+    let mut regs = TEST_VALUES;
+    INVERT_ON_BREAKPOINT.store(true, Ordering::Relaxed);
+    unsafe { breakpoint_with(&mut regs) };
+    INVERT_ON_BREAKPOINT.store(false, Ordering::Relaxed);
+    for (reg, value) in regs.iter().zip(TEST_VALUES.iter()) {
+        assert_eq!(*reg, !*value);
+    }
+}
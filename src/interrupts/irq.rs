@@ -0,0 +1,220 @@
+use alloc::sync::Arc;
+use core::{mem, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+
+use x86_64::{instructions::interrupts, structures::idt::{HandlerFunc, InterruptDescriptorTable, InterruptStackFrame}};
+
+use crate::{apic, serial_println};
+
+use super::{PIC_1_OFFSET, PICS};
+
+// the 8259s deliver 16 lines, the I/O APIC routes up to 24
+pub const IRQ_COUNT: usize = 24;
+// handlers that can share one line
+const MAX_SHARED: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqStatus {
+    Handled,
+    // a shared line fired for another device
+    NotMine
+}
+
+// the generation tells a stale id apart from a newer handler in its slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqHandlerId {
+    irq: u8,
+    slot: usize,
+    generation: u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    InvalidIrq,
+    LineFull
+}
+
+// cheap to clone, so the dispatcher can copy a line out of the registry
+#[derive(Clone)]
+enum Handler {
+    Function(fn(u8) -> IrqStatus),
+    Closure(Arc<dyn Fn(u8) -> IrqStatus + Send + Sync>)
+}
+
+#[derive(Clone)]
+struct Entry {
+    name: &'static str,
+    generation: u64,
+    handler: Handler
+}
+
+impl Entry {
+    fn call(&self, irq: u8) -> IrqStatus {
+        match &self.handler {
+            Handler::Function(handler) => handler(irq),
+            Handler::Closure(handler) => handler(irq)
+        }
+    }
+}
+
+const NO_ENTRY: Option<Entry> = None;
+const EMPTY_LINE: [Option<Entry>; MAX_SHARED] = [NO_ENTRY; MAX_SHARED];
+const MAX_RETIRED: usize = 8;
+
+struct Registry {
+    lines: [[Option<Entry>; MAX_SHARED]; IRQ_COUNT],
+    // handlers unregistered from inside `dispatch`, which still holds a
+    // reference to them; freeing memory there could wait for the allocator
+    // lock forever, so they are dropped by the next change made outside
+    retired: [Option<Entry>; MAX_RETIRED],
+    next_generation: u64
+}
+
+// only changed with interrupts disabled, so the dispatcher never finds it
+// locked on this CPU
+static HANDLERS: spin::Mutex<Registry> = spin::Mutex::new(Registry {
+    lines: [EMPTY_LINE; IRQ_COUNT],
+    retired: [NO_ENTRY; MAX_RETIRED],
+    next_generation: 0
+});
+static DISPATCHING: AtomicBool = AtomicBool::new(false);
+static UNHANDLED: AtomicUsize = AtomicUsize::new(0);
+
+extern "x86-interrupt" fn trampoline<const IRQ: u8>(_stack_frame: InterruptStackFrame) {
+    dispatch(IRQ);
+    end_of_interrupt(IRQ);
+}
+
+const TRAMPOLINES: [HandlerFunc; IRQ_COUNT] = [
+    trampoline::<0>, trampoline::<1>, trampoline::<2>, trampoline::<3>,
+    trampoline::<4>, trampoline::<5>, trampoline::<6>, trampoline::<7>,
+    trampoline::<8>, trampoline::<9>, trampoline::<10>, trampoline::<11>,
+    trampoline::<12>, trampoline::<13>, trampoline::<14>, trampoline::<15>,
+    trampoline::<16>, trampoline::<17>, trampoline::<18>, trampoline::<19>,
+    trampoline::<20>, trampoline::<21>, trampoline::<22>, trampoline::<23>
+];
+
+pub fn vector(irq: u8) -> u8 {
+    PIC_1_OFFSET + irq
+}
+
+pub(super) fn install_trampolines(idt: &mut InterruptDescriptorTable) {
+    for (irq, &trampoline) in TRAMPOLINES.iter().enumerate() {
+        idt[usize::from(vector(irq as u8))].set_handler_fn(trampoline);
+    }
+}
+
+// runs every handler on the line without acknowledging the interrupt, the
+// trampolines send the EOI afterwards
+pub fn dispatch(irq: u8) {
+    // the handlers run without the lock held, so they can change the registry
+    let line = interrupts::without_interrupts(|| HANDLERS.lock().lines[usize::from(irq)].clone());
+    DISPATCHING.store(true, Ordering::Relaxed);
+    let mut handled = false;
+    for entry in line.iter().flatten() {
+        handled |= entry.call(irq) == IrqStatus::Handled;
+    }
+    DISPATCHING.store(false, Ordering::Relaxed);
+    if !handled {
+        UNHANDLED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// the 8259 is only in charge until the APIC takes over
+fn end_of_interrupt(irq: u8) {
+    match apic::local_apic() {
+        Some(local_apic) => local_apic.end_of_interrupt(),
+        None => unsafe { PICS.lock().notify_end_of_interrupt(vector(irq)) }
+    }
+}
+
+pub fn register(irq: u8, name: &'static str, handler: fn(u8) -> IrqStatus)
+    -> Result<IrqHandlerId, IrqError> {
+    add(irq, name, Handler::Function(handler))
+}
+
+pub fn register_closure<F>(irq: u8, name: &'static str, handler: F) -> Result<IrqHandlerId, IrqError>
+where
+    F: Fn(u8) -> IrqStatus + Send + Sync + 'static
+{
+    add(irq, name, Handler::Closure(Arc::new(handler)))
+}
+
+fn add(irq: u8, name: &'static str, handler: Handler) -> Result<IrqHandlerId, IrqError> {
+    if usize::from(irq) >= IRQ_COUNT {
+        return Err(IrqError::InvalidIrq);
+    }
+    let (id, retired) = interrupts::without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let generation = handlers.next_generation;
+        let line = &mut handlers.lines[usize::from(irq)];
+        let slot = line.iter().position(Option::is_none).ok_or(IrqError::LineFull)?;
+        let first = line.iter().all(Option::is_none);
+        line[slot] = Some(Entry { name, generation, handler });
+        handlers.next_generation += 1;
+        if first {
+            apic::route_irq(irq, vector(irq));
+        }
+        Ok((IrqHandlerId { irq, slot, generation }, take_retired(&mut handlers)))
+    })?;
+    drop(retired);
+    Ok(id)
+}
+
+// the line is masked again once its last handler is gone
+pub fn unregister(id: IrqHandlerId) -> bool {
+    let (removed, retired) = interrupts::without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let line = &mut handlers.lines[usize::from(id.irq)];
+        let entry = match &line[id.slot] {
+            Some(entry) if entry.generation == id.generation => line[id.slot].take(),
+            _ => None
+        };
+        if entry.is_some() && line.iter().all(Option::is_none) {
+            apic::mask_irq(id.irq);
+        }
+        let removed = entry.is_some();
+        if let Some(entry) = entry {
+            match handlers.retired.iter_mut().find(|slot| slot.is_none()) {
+                Some(slot) => *slot = Some(entry),
+                // better a leak than freeing inside an interrupt
+                None => mem::forget(entry)
+            }
+        }
+        (removed, take_retired(&mut handlers))
+    });
+    // closures are freed outside of the critical section
+    drop(retired);
+    removed
+}
+
+// nothing to take while a dispatch may still use them
+fn take_retired(handlers: &mut Registry) -> [Option<Entry>; MAX_RETIRED] {
+    if DISPATCHING.load(Ordering::Relaxed) {
+        return [NO_ENTRY; MAX_RETIRED];
+    }
+    mem::replace(&mut handlers.retired, [NO_ENTRY; MAX_RETIRED])
+}
+
+// routes every line that got handlers before the APIC was set up
+pub(crate) fn route_registered() {
+    let handlers = HANDLERS.lock();
+    for (irq, line) in handlers.lines.iter().enumerate() {
+        if line.iter().any(Option::is_some) {
+            apic::route_irq(irq as u8, vector(irq as u8));
+        }
+    }
+}
+
+pub fn unhandled_count() -> usize {
+    UNHANDLED.load(Ordering::Relaxed)
+}
+
+pub fn dump() {
+    interrupts::without_interrupts(|| {
+        for (irq, line) in HANDLERS.lock().lines.iter().enumerate() {
+            for entry in line.iter().flatten() {
+                serial_println!("irq {:>2} vector {:>3} {}", irq, vector(irq as u8), entry.name);
+            }
+        }
+    });
+}
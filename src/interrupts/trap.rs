@@ -0,0 +1,159 @@
+use core::{arch::naked_asm, fmt};
+
+// what the entry stubs leave on the stack, lowest address first; the last
+// five fields are pushed by the CPU itself
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub gs: u64,
+    pub fs: u64,
+    pub es: u64,
+    pub ds: u64,
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    // zero for exceptions without one
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rax {:016x} rbx {:016x} rcx {:016x} rdx {:016x}", self.rax, self.rbx, self.rcx, self.rdx)?;
+        writeln!(f, "rsi {:016x} rdi {:016x} rbp {:016x} rsp {:016x}", self.rsi, self.rdi, self.rbp, self.rsp)?;
+        writeln!(f, "r8  {:016x} r9  {:016x} r10 {:016x} r11 {:016x}", self.r8, self.r9, self.r10, self.r11)?;
+        writeln!(f, "r12 {:016x} r13 {:016x} r14 {:016x} r15 {:016x}", self.r12, self.r13, self.r14, self.r15)?;
+        writeln!(f, "rip {:016x} rflags {:08x}", self.rip, self.rflags)?;
+        write!(f, "cs {:04x} ss {:04x} ds {:04x} es {:04x} fs {:04x} gs {:04x}",
+            self.cs, self.ss, self.ds, self.es, self.fs, self.gs)
+    }
+}
+
+// pushes a zero for vectors where the CPU does not push an error code, so
+// every frame looks the same
+macro_rules! trap_stub {
+    ($stub:ident, $vector:expr) => {
+        #[unsafe(naked)]
+        pub(super) extern "C" fn $stub() {
+            naked_asm!(
+                "push 0",
+                "push {vector}",
+                "jmp {common}",
+                vector = const $vector,
+                common = sym trap_common
+            )
+        }
+    };
+    ($stub:ident, $vector:expr, error_code) => {
+        #[unsafe(naked)]
+        pub(super) extern "C" fn $stub() {
+            naked_asm!(
+                "push {vector}",
+                "jmp {common}",
+                vector = const $vector,
+                common = sym trap_common
+            )
+        }
+    };
+}
+
+// saves the registers into a `TrapFrame`, hands it to `trap_dispatch` and
+// returns with whatever the handler left in it; fs and gs are saved but not
+// reloaded, since loading a selector would reset their base
+#[unsafe(naked)]
+extern "C" fn trap_common() {
+    naked_asm!(
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov rax, ds",
+        "push rax",
+        "mov rax, es",
+        "push rax",
+        "mov rax, fs",
+        "push rax",
+        "mov rax, gs",
+        "push rax",
+        // 26 quadwords on a stack the CPU aligned to 16 bytes
+        "mov rdi, rsp",
+        "cld",
+        "call {dispatch}",
+        "add rsp, 16",
+        "pop rax",
+        "mov es, ax",
+        "pop rax",
+        "mov ds, ax",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        // vector and error code
+        "add rsp, 16",
+        "iretq",
+        dispatch = sym super::exceptions::trap_dispatch
+    )
+}
+
+trap_stub!(divide_error, 0);
+trap_stub!(debug, 1);
+trap_stub!(non_maskable_interrupt, 2);
+trap_stub!(breakpoint, 3);
+trap_stub!(overflow, 4);
+trap_stub!(bound_range_exceeded, 5);
+trap_stub!(invalid_opcode, 6);
+trap_stub!(device_not_available, 7);
+trap_stub!(double_fault, 8, error_code);
+trap_stub!(invalid_tss, 10, error_code);
+trap_stub!(segment_not_present, 11, error_code);
+trap_stub!(stack_segment_fault, 12, error_code);
+trap_stub!(general_protection_fault, 13, error_code);
+trap_stub!(page_fault, 14, error_code);
+trap_stub!(x87_floating_point, 16);
+trap_stub!(alignment_check, 17, error_code);
+trap_stub!(machine_check, 18);
+trap_stub!(simd_floating_point, 19);
+trap_stub!(virtualization, 20);
+trap_stub!(cp_protection_exception, 21, error_code);
+trap_stub!(hv_injection_exception, 28);
+trap_stub!(vmm_communication_exception, 29, error_code);
+trap_stub!(security_exception, 30, error_code);
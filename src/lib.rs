@@ -10,12 +10,17 @@
 extern crate alloc;
 
 pub mod serial;
+pub mod acpi;
+pub mod apic;
 pub mod vga_buffer;
 pub mod interrupts;
 pub mod gdt;
 pub mod memory;
 pub mod allocator;
 pub mod task;
+pub mod backtrace;
+pub mod cpu;
+pub mod time;
 
 use core::panic::PanicInfo;
 
@@ -92,11 +97,13 @@ fn panic(_info: &PanicInfo) -> ! {
 }
 
 pub fn init() {
+    cpu::init();
     gdt::init();
     interrupts::init_idt();
     unsafe {
         interrupts::PICS.lock().initialize();
     }
+    time::set_frequency(time::DEFAULT_FREQUENCY);
     x86_64::instructions::interrupts::enable();
 }
 
@@ -7,7 +7,7 @@
 extern crate alloc;
 
 use core::panic::PanicInfo;
-use blog_os::{println, memory::BootInfoFrameAllocator, allocator, task::{Task, self, executor::Executor}};
+use blog_os::{println, memory::bitmap::BitmapFrameAllocator, allocator, task::{Task, self, executor::Executor}};
 use bootloader::{BootInfo, entry_point};
 use x86_64::VirtAddr;
 
@@ -18,12 +18,22 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 
     blog_os::init();
 
+    blog_os::memory::regions::init(&boot_info.memory_map);
+    blog_os::memory::regions::dump();
+
     let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { blog_os::memory::init(physical_memory_offset) };
-    let mut frame_allocator = BootInfoFrameAllocator::init(&boot_info.memory_map);
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
 
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap initialization failed");
+    blog_os::memory::init_kernel_memory(mapper, frame_allocator);
+    blog_os::gdt::init_stacks().expect("interrupt stack initialization failed");
+    if let Err(err) = blog_os::apic::init() {
+        println!("staying on the 8259, no APIC: {:?}", err);
+    }
 
     // let mut executor = SimpleExecutor::new();
     let mut executor = Executor::new();
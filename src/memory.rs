@@ -1,11 +1,85 @@
+pub mod bitmap;
+pub mod demand;
+pub mod buddy;
+pub mod guard;
+pub mod inspect;
+pub mod paging;
+pub mod regions;
+pub mod sections;
+pub mod stack;
+pub mod vma;
+
+use core::ops::RangeBounds;
+
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
-use x86_64::{registers::control::Cr3, VirtAddr, structures::paging::{PageTable, page_table::FrameError, OffsetPageTable, PhysFrame, PageTableFlags, Mapper, Page, FrameAllocator, Size4KiB}, PhysAddr};
+use conquer_once::spin::OnceCell;
+use x86_64::{registers::control::Cr3, VirtAddr, structures::paging::{PageTable, mapper::MappedFrame, OffsetPageTable, PhysFrame, PageTableFlags, Mapper, Page, FrameAllocator, Size4KiB}, PhysAddr};
+
+use crate::serial_println;
+
+use self::bitmap::BitmapFrameAllocator;
 
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
     let l4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(l4_table, physical_memory_offset)
 }
 
+pub struct KernelMemory {
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: BitmapFrameAllocator
+}
+
+// the heap takes this lock when it grows, so nothing may allocate while
+// holding it
+static KERNEL_MEMORY: OnceCell<spin::Mutex<KernelMemory>> = OnceCell::uninit();
+// readable without the lock, for code that may run while somebody holds it
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+pub fn init_kernel_memory(
+    mapper: OffsetPageTable<'static>, frame_allocator: BitmapFrameAllocator
+) {
+    let phys_offset = mapper.phys_offset();
+    KERNEL_MEMORY.try_init_once(|| spin::Mutex::new(KernelMemory { mapper, frame_allocator }))
+        .expect("init_kernel_memory should be called only once");
+    PHYSICAL_MEMORY_OFFSET.init_once(|| phys_offset);
+    let stacks = VirtAddr::new(stack::KERNEL_STACKS_START);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    vma::reserve("kernel stacks", stacks, stack::KERNEL_STACKS_SIZE, flags, vma::VmaKind::Reserved)
+        .expect("kernel stack range is already in use");
+    sections::protect_kernel_sections().expect("remapping the kernel image failed");
+}
+
+pub fn kernel_memory() -> Option<spin::MutexGuard<'static, KernelMemory>> {
+    KERNEL_MEMORY.try_get().ok().map(|memory| memory.lock())
+}
+
+pub fn try_kernel_memory() -> Option<spin::MutexGuard<'static, KernelMemory>> {
+    KERNEL_MEMORY.try_get().ok()?.try_lock()
+}
+
+// false as long as the kernel memory is not set up, there is no offset to
+// reach the page tables through then
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    match PHYSICAL_MEMORY_OFFSET.try_get() {
+        Ok(&offset) => translate_addr_inner(addr, offset).is_some(),
+        Err(_) => false
+    }
+}
+
+// prints the present mappings inside `range` to the serial port, `..` dumps
+// the whole address space
+pub fn dump_page_tables(range: impl RangeBounds<VirtAddr>) -> Option<inspect::PageTableSummary> {
+    // held so that nobody changes the tables during the walk
+    let memory = kernel_memory()?;
+    let summary = unsafe {
+        inspect::walk_page_tables(range, memory.mapper.phys_offset(), |mapping| {
+            serial_println!("{}", mapping);
+        })
+    };
+    serial_println!("{}", summary);
+    Some(summary)
+}
+
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr)
     -> &'static mut PageTable {
     let (level_4_table_frame, _) = Cr3::read();
@@ -28,15 +102,35 @@ pub fn create_example_mapping(
     map_to_result.expect("map_to failed").flush();
 }
 
+#[derive(Debug)]
+pub struct Translation {
+    pub phys_addr: PhysAddr,
+    pub frame: MappedFrame,
+    pub flags: PageTableFlags
+}
+
+impl Translation {
+    pub fn page_size(&self) -> u64 {
+        self.frame.size()
+    }
+}
+
 pub unsafe fn translate_addr(
     virtual_addr: VirtAddr, physical_memory_offset: VirtAddr
 ) -> Option<PhysAddr> {
+    translate_addr_inner(virtual_addr, physical_memory_offset)
+        .map(|translation| translation.phys_addr)
+}
+
+pub unsafe fn translate(
+    virtual_addr: VirtAddr, physical_memory_offset: VirtAddr
+) -> Option<Translation> {
     translate_addr_inner(virtual_addr, physical_memory_offset)
 }
 
 fn translate_addr_inner(
     virtual_addr: VirtAddr, physical_memory_offset: VirtAddr
-) -> Option<PhysAddr> {
+) -> Option<Translation> {
     let (level_4_table_frame, _) = Cr3::read();
     let table_idxs = [
         virtual_addr.p4_index(),
@@ -45,21 +139,46 @@ fn translate_addr_inner(
         virtual_addr.p1_index()
     ];
     let mut frame = level_4_table_frame;
-    
-    for idx in table_idxs {
-        let virtual_addr = physical_memory_offset + frame.start_address().as_u64();
-        let page_table_ptr: *const PageTable = virtual_addr.as_ptr();
+    // writable/user must be granted at every level, NX at any level wins
+    let mut writable_user = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    let mut no_execute = PageTableFlags::empty();
+
+    for (level, &idx) in table_idxs.iter().enumerate() {
+        let table_addr = physical_memory_offset + frame.start_address().as_u64();
+        let page_table_ptr: *const PageTable = table_addr.as_ptr();
         let page_table = unsafe {
             &*page_table_ptr
         };
         let entry = &page_table[idx];
-        frame = match entry.frame() {
-            Ok(frame) => frame,
-            Err(FrameError::HugeFrame) => panic!("huge pages not supported"),
-            Err(FrameError::FrameNotPresent) => return None,
+        let entry_flags = entry.flags();
+        if !entry_flags.contains(PageTableFlags::PRESENT) {
+            return None;
         }
+        writable_user &= entry_flags;
+        no_execute |= entry_flags & PageTableFlags::NO_EXECUTE;
+        let flags = entry_flags
+            - (PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE)
+            | writable_user | no_execute;
+
+        let is_huge = entry_flags.contains(PageTableFlags::HUGE_PAGE);
+        let mapped = match level {
+            0 if is_huge => return None,
+            1 if is_huge => MappedFrame::Size1GiB(PhysFrame::containing_address(entry.addr())),
+            2 if is_huge => MappedFrame::Size2MiB(PhysFrame::containing_address(entry.addr())),
+            3 => MappedFrame::Size4KiB(PhysFrame::containing_address(entry.addr())),
+            _ => {
+                frame = PhysFrame::containing_address(entry.addr());
+                continue;
+            }
+        };
+        let offset = virtual_addr.as_u64() & (mapped.size() - 1);
+        return Some(Translation {
+            phys_addr: mapped.start_address() + offset,
+            frame: mapped,
+            flags
+        });
     }
-    Some(frame.start_address() + u64::from(virtual_addr.page_offset()))
+    None
 }
 
 pub struct EmptyFrameAllocator;
@@ -0,0 +1,137 @@
+use core::slice;
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::{PhysAddr, VirtAddr, structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB}};
+
+const FRAME_SIZE: u64 = Size4KiB::SIZE;
+const BITS_PER_WORD: usize = 64;
+
+// one bit per 4 KiB frame, a set bit means the frame is in use or not usable
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    // set for the frames that can be handed out, that is the ones in usable
+    // regions that do not hold the bitmaps
+    usable: &'static mut [u64],
+    total_frames: usize,
+    free_frames: usize,
+    next: usize
+}
+
+impl BitmapFrameAllocator {
+    pub unsafe fn init(
+        memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr
+    ) -> Self {
+        let usable_regions = || memory_map.iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable);
+
+        let max_addr = usable_regions().map(|r| r.range.end_addr()).max()
+            .unwrap_or(0);
+        let n_frames = (max_addr / FRAME_SIZE) as usize;
+        let n_words = (n_frames + BITS_PER_WORD - 1) / BITS_PER_WORD;
+        // the usable bitmap follows the allocation bitmap
+        let bitmap_size = (2 * n_words * 8) as u64;
+        let bitmap_frames = (bitmap_size + FRAME_SIZE - 1) / FRAME_SIZE;
+
+        let bitmap_start = usable_regions()
+            .find(|r| r.range.end_addr() - r.range.start_addr() >= bitmap_frames * FRAME_SIZE)
+            .expect("no usable region large enough for the frame bitmap")
+            .range.start_addr();
+        let bitmap_ptr: *mut u64 = (physical_memory_offset + bitmap_start).as_mut_ptr();
+        let bitmap = slice::from_raw_parts_mut(bitmap_ptr, n_words);
+        bitmap.fill(!0);
+        let usable = slice::from_raw_parts_mut(bitmap_ptr.add(n_words), n_words);
+        usable.fill(0);
+
+        let bitmap_first = (bitmap_start / FRAME_SIZE) as usize;
+        let bitmap_frames = bitmap_first..bitmap_first + bitmap_frames as usize;
+        let mut allocator = BitmapFrameAllocator {
+            bitmap, usable, total_frames: 0, free_frames: 0, next: 0
+        };
+        for region in usable_regions() {
+            let start = region.range.start_addr() / FRAME_SIZE;
+            let end = region.range.end_addr() / FRAME_SIZE;
+            for idx in start..end {
+                allocator.clear(idx as usize);
+                allocator.set_usable(idx as usize, true);
+                allocator.total_frames += 1;
+            }
+        }
+        for idx in bitmap_frames {
+            allocator.set(idx);
+            allocator.set_usable(idx, false);
+            allocator.total_frames -= 1;
+        }
+        allocator.free_frames = allocator.total_frames;
+        allocator
+    }
+
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames
+    }
+
+    // whether the frame was ever handed out
+    fn is_allocatable(&self, idx: usize) -> bool {
+        self.usable[idx / BITS_PER_WORD] & (1 << (idx % BITS_PER_WORD)) != 0
+    }
+
+    fn set_usable(&mut self, idx: usize, usable: bool) {
+        if usable {
+            self.usable[idx / BITS_PER_WORD] |= 1 << (idx % BITS_PER_WORD);
+        } else {
+            self.usable[idx / BITS_PER_WORD] &= !(1 << (idx % BITS_PER_WORD));
+        }
+    }
+
+    fn is_set(&self, idx: usize) -> bool {
+        self.bitmap[idx / BITS_PER_WORD] & (1 << (idx % BITS_PER_WORD)) != 0
+    }
+
+    fn set(&mut self, idx: usize) {
+        self.bitmap[idx / BITS_PER_WORD] |= 1 << (idx % BITS_PER_WORD);
+    }
+
+    fn clear(&mut self, idx: usize) {
+        self.bitmap[idx / BITS_PER_WORD] &= !(1 << (idx % BITS_PER_WORD));
+    }
+
+    fn find_free_word(&self) -> Option<usize> {
+        let n_words = self.bitmap.len();
+        (self.next..n_words).chain(0..self.next)
+            .find(|&word| self.bitmap[word] != !0)
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if self.free_frames == 0 {
+            return None;
+        }
+        let word = self.find_free_word()?;
+        let bit = (!self.bitmap[word]).trailing_zeros() as usize;
+        let idx = word * BITS_PER_WORD + bit;
+        self.set(idx);
+        self.free_frames -= 1;
+        self.next = word;
+        Some(PhysFrame::containing_address(PhysAddr::new(idx as u64 * FRAME_SIZE)))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let idx = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        assert!(idx / BITS_PER_WORD < self.bitmap.len(), "frame {:?} out of range", frame);
+        assert!(self.is_allocatable(idx), "frame {:?} is not usable memory", frame);
+        assert!(self.is_set(idx), "frame {:?} freed twice", frame);
+        self.clear(idx);
+        self.free_frames += 1;
+        self.next = self.next.min(idx / BITS_PER_WORD);
+    }
+}
@@ -0,0 +1,290 @@
+use core::slice;
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::{PhysAddr, VirtAddr, structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size1GiB, Size2MiB, Size4KiB, frame::PhysFrameRange}};
+
+const FRAME_SIZE: u64 = Size4KiB::SIZE;
+const BITS_PER_WORD: usize = 64;
+const ORDER_2MIB: usize = 9;
+const ORDER_1GIB: usize = 18;
+pub const MAX_ORDER: usize = ORDER_1GIB;
+
+struct FreeBlock {
+    prev: Option<PhysFrame>,
+    next: Option<PhysFrame>
+}
+
+// one bit per block of every order, the orders are stored one after the other
+struct OrderBitmap {
+    words: &'static mut [u64],
+    offsets: [usize; MAX_ORDER + 1],
+    n_frames: usize
+}
+
+impl OrderBitmap {
+    fn order_words(n_frames: usize, order: usize) -> usize {
+        let blocks = (n_frames + (1 << order) - 1) >> order;
+        (blocks + BITS_PER_WORD - 1) / BITS_PER_WORD
+    }
+
+    fn words_needed(n_frames: usize) -> usize {
+        (0..=MAX_ORDER).map(|order| Self::order_words(n_frames, order)).sum()
+    }
+
+    unsafe fn new(ptr: *mut u64, n_frames: usize) -> Self {
+        let mut offsets = [0; MAX_ORDER + 1];
+        let mut n_words = 0;
+        for (order, offset) in offsets.iter_mut().enumerate() {
+            *offset = n_words;
+            n_words += Self::order_words(n_frames, order);
+        }
+        let words = slice::from_raw_parts_mut(ptr, n_words);
+        words.fill(0);
+        OrderBitmap { words, offsets, n_frames }
+    }
+
+    fn position(&self, order: usize, idx: usize) -> (usize, u64) {
+        let block = idx >> order;
+        (self.offsets[order] + block / BITS_PER_WORD, 1 << (block % BITS_PER_WORD))
+    }
+
+    fn get(&self, order: usize, idx: usize) -> bool {
+        if idx >= self.n_frames {
+            return false;
+        }
+        let (word, mask) = self.position(order, idx);
+        self.words[word] & mask != 0
+    }
+
+    fn set(&mut self, order: usize, idx: usize, value: bool) {
+        let (word, mask) = self.position(order, idx);
+        if value {
+            self.words[word] |= mask;
+        } else {
+            self.words[word] &= !mask;
+        }
+    }
+}
+
+pub struct BuddyFrameAllocator {
+    physical_memory_offset: VirtAddr,
+    free_lists: [Option<PhysFrame>; MAX_ORDER + 1],
+    // which blocks are on a free list, so buddies are found without a walk
+    free_map: OrderBitmap,
+    // which blocks were handed out, only those can be freed
+    allocated_map: OrderBitmap,
+    total_frames: usize,
+    free_frames: usize
+}
+
+impl BuddyFrameAllocator {
+    pub unsafe fn init(
+        memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr
+    ) -> Self {
+        let usable_regions = || memory_map.iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable);
+
+        let max_addr = usable_regions().map(|r| r.range.end_addr()).max()
+            .unwrap_or(0);
+        let n_frames = (max_addr / FRAME_SIZE) as usize;
+        let n_words = OrderBitmap::words_needed(n_frames);
+        let map_frames = ((2 * n_words * 8) as u64 + FRAME_SIZE - 1) / FRAME_SIZE;
+
+        let map_start = usable_regions()
+            .find(|r| r.range.end_addr() - r.range.start_addr() >= map_frames * FRAME_SIZE)
+            .expect("no usable region large enough for the buddy bitmaps")
+            .range.start_addr();
+        let map_ptr: *mut u64 = (physical_memory_offset + map_start).as_mut_ptr();
+
+        let mut allocator = BuddyFrameAllocator {
+            physical_memory_offset,
+            free_lists: [None; MAX_ORDER + 1],
+            free_map: OrderBitmap::new(map_ptr, n_frames),
+            allocated_map: OrderBitmap::new(map_ptr.add(n_words), n_frames),
+            total_frames: 0,
+            free_frames: 0
+        };
+        let map_first = map_start / FRAME_SIZE;
+        for region in usable_regions() {
+            let mut start = region.range.start_addr() / FRAME_SIZE;
+            let end = region.range.end_addr() / FRAME_SIZE;
+            // the bitmaps sit at the start of their region
+            if start == map_first {
+                start += map_frames;
+            }
+            while start < end {
+                let mut order = (start.trailing_zeros() as usize).min(MAX_ORDER);
+                while start + (1 << order) > end {
+                    order -= 1;
+                }
+                let frame = PhysFrame::containing_address(PhysAddr::new(start * FRAME_SIZE));
+                allocator.release_block(frame, order);
+                allocator.total_frames += 1 << order;
+                start += 1 << order;
+            }
+        }
+        allocator
+    }
+
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames
+    }
+
+    pub fn largest_free_order(&self) -> Option<usize> {
+        (0..=MAX_ORDER).rev().find(|&order| self.free_lists[order].is_some())
+    }
+
+    pub fn order_for(count: usize) -> usize {
+        count.next_power_of_two().trailing_zeros() as usize
+    }
+
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrameRange> {
+        if count == 0 {
+            return None;
+        }
+        let order = Self::order_for(count);
+        if order > MAX_ORDER {
+            return None;
+        }
+        let start = self.allocate_block(order)?;
+        Some(PhysFrame::range(start, start + (1 << order)))
+    }
+
+    pub unsafe fn deallocate_contiguous(&mut self, range: PhysFrameRange) {
+        let count = range.end - range.start;
+        assert!(count.is_power_of_two(), "range of {} frames is not a buddy block", count);
+        self.free_block(range.start, count.trailing_zeros() as usize);
+    }
+
+    fn frame_index(frame: PhysFrame) -> usize {
+        (frame.start_address().as_u64() / FRAME_SIZE) as usize
+    }
+
+    fn allocate_block(&mut self, order: usize) -> Option<PhysFrame> {
+        let mut found = (order..=MAX_ORDER).find(|&k| self.free_lists[k].is_some())?;
+        let block = self.pop(found).unwrap();
+        while found > order {
+            found -= 1;
+            self.push(found, block + (1 << found));
+        }
+        self.allocated_map.set(order, Self::frame_index(block), true);
+        Some(block)
+    }
+
+    // gives back a block exactly as `allocate_block` handed it out
+    fn free_block(&mut self, frame: PhysFrame, order: usize) {
+        let idx = Self::frame_index(frame);
+        assert!(order <= MAX_ORDER && idx < self.allocated_map.n_frames,
+            "frame {:?} out of range", frame);
+        assert_eq!(idx & ((1 << order) - 1), 0, "frame {:?} is not aligned to order {}", frame, order);
+        assert!(self.allocated_map.get(order, idx),
+            "block of order {} at {:?} was not allocated or is freed twice", order, frame);
+        self.allocated_map.set(order, idx, false);
+        self.release_block(frame, order);
+    }
+
+    fn release_block(&mut self, frame: PhysFrame, order: usize) {
+        let mut block = frame;
+        let mut order = order;
+        while order < MAX_ORDER {
+            let buddy_addr = block.start_address().as_u64() ^ (FRAME_SIZE << order);
+            let buddy = PhysFrame::containing_address(PhysAddr::new(buddy_addr));
+            if !self.remove(order, buddy) {
+                break;
+            }
+            block = block.min(buddy);
+            order += 1;
+        }
+        self.push(order, block);
+    }
+
+    fn block_ptr(&self, frame: PhysFrame) -> *mut FreeBlock {
+        (self.physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr()
+    }
+
+    fn push(&mut self, order: usize, frame: PhysFrame) {
+        let head = self.free_lists[order];
+        unsafe {
+            self.block_ptr(frame).write(FreeBlock { prev: None, next: head });
+            if let Some(head) = head {
+                (*self.block_ptr(head)).prev = Some(frame);
+            }
+        }
+        self.free_lists[order] = Some(frame);
+        self.free_map.set(order, Self::frame_index(frame), true);
+        self.free_frames += 1 << order;
+    }
+
+    fn pop(&mut self, order: usize) -> Option<PhysFrame> {
+        let frame = self.free_lists[order]?;
+        self.remove(order, frame);
+        Some(frame)
+    }
+
+    fn remove(&mut self, order: usize, frame: PhysFrame) -> bool {
+        if !self.free_map.get(order, Self::frame_index(frame)) {
+            return false;
+        }
+        unsafe {
+            let block = self.block_ptr(frame).read();
+            match block.prev {
+                Some(prev) => (*self.block_ptr(prev)).next = block.next,
+                None => self.free_lists[order] = block.next
+            }
+            if let Some(next) = block.next {
+                (*self.block_ptr(next)).prev = block.prev;
+            }
+        }
+        self.free_map.set(order, Self::frame_index(frame), false);
+        self.free_frames -= 1 << order;
+        true
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        self.allocate_block(0)
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let block = self.allocate_block(ORDER_2MIB)?;
+        Some(PhysFrame::containing_address(block.start_address()))
+    }
+}
+
+unsafe impl FrameAllocator<Size1GiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size1GiB>> {
+        let block = self.allocate_block(ORDER_1GIB)?;
+        Some(PhysFrame::containing_address(block.start_address()))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        self.free_block(frame, 0);
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let block = PhysFrame::containing_address(frame.start_address());
+        self.free_block(block, ORDER_2MIB);
+    }
+}
+
+impl FrameDeallocator<Size1GiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size1GiB>) {
+        let block = PhysFrame::containing_address(frame.start_address());
+        self.free_block(block, ORDER_1GIB);
+    }
+}
@@ -0,0 +1,55 @@
+use x86_64::{VirtAddr, structures::{idt::PageFaultErrorCode, paging::{Page, PageSize, Size4KiB, FrameAllocator, FrameDeallocator, Mapper, PageTableFlags}}};
+
+use super::{kernel_memory, try_kernel_memory, paging, KernelMemory, vma::{self, Vma, VmaError, VmaKind}};
+
+// reserves address space whose pages only get backed when first touched
+pub fn reserve(name: &'static str, size: u64, flags: PageTableFlags) -> Result<Vma, VmaError> {
+    let size = (size + Size4KiB::SIZE - 1) & !(Size4KiB::SIZE - 1);
+    vma::allocate(name, size, Size4KiB::SIZE, flags, VmaKind::Demand)
+}
+
+// unmaps whatever was touched and gives the address space back
+pub fn release(vma: Vma) {
+    if let Some(mut memory) = kernel_memory() {
+        let KernelMemory { mapper, frame_allocator } = &mut *memory;
+        paging::unmap_range(paging::page_range(vma.start, vma.size()), mapper, frame_allocator);
+    }
+    vma::release(vma.start);
+}
+
+// maps a zeroed frame under `addr` if it belongs to a demand paged area,
+// returns false when the fault is a real error
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return false;
+    }
+    let vma = match vma::find(addr) {
+        Some(vma) if vma.kind == VmaKind::Demand => vma,
+        _ => return false
+    };
+    // the fault may have hit code that holds the lock, waiting would hang
+    let mut memory = match try_kernel_memory() {
+        Some(memory) => memory,
+        None => return false
+    };
+    let KernelMemory { mapper, frame_allocator } = &mut *memory;
+    let frame = match frame_allocator.allocate_frame() {
+        Some(frame) => frame,
+        None => return false
+    };
+    let frame_ptr: *mut u8 = (mapper.phys_offset() + frame.start_address().as_u64()).as_mut_ptr();
+    unsafe { frame_ptr.write_bytes(0, Size4KiB::SIZE as usize) };
+
+    let page = Page::<Size4KiB>::containing_address(addr);
+    let flags = vma.flags | PageTableFlags::PRESENT;
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            true
+        }
+        Err(_) => {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            false
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use x86_64::VirtAddr;
+
+use super::vma::{self, VmaKind};
+
+// an unmapped range that only an overflow of its neighbour can run into
+#[derive(Debug, Clone, Copy)]
+pub struct GuardRegion {
+    pub name: &'static str,
+    pub start: VirtAddr,
+    pub end: VirtAddr
+}
+
+// guards are `VmaKind::Guard` areas named after what they protect, except
+// for the kernel stacks, whose guards move with the stack sizes
+pub fn find_guard(addr: VirtAddr) -> Option<GuardRegion> {
+    match vma::find(addr) {
+        Some(vma) if vma.kind == VmaKind::Guard => {
+            Some(GuardRegion { name: vma.name, start: vma.start, end: vma.end })
+        }
+        _ => super::stack::find_guard(addr)
+    }
+}
@@ -0,0 +1,175 @@
+use core::{fmt, ops::{Bound, RangeBounds}};
+
+use x86_64::{PhysAddr, VirtAddr, registers::control::Cr3, structures::paging::{PageTable, PageTableFlags}};
+
+// set by the CPU on every access, they would split otherwise identical runs
+const IGNORED_FLAGS: PageTableFlags = PageTableFlags::ACCESSED.union(PageTableFlags::DIRTY);
+
+// a run of leaf entries on the same level that map contiguous memory with
+// the same flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    // 1 for 4KiB pages, 2 for 2MiB and 3 for 1GiB pages
+    pub level: u8,
+    pub start: VirtAddr,
+    pub size: u64,
+    pub frame: PhysAddr,
+    pub flags: PageTableFlags,
+    pub entries: usize
+}
+
+impl Mapping {
+    pub fn page_size(&self) -> u64 {
+        entry_size(self.level)
+    }
+
+    fn extend(&mut self, next: &Mapping) -> bool {
+        let contiguous = self.start.as_u64().wrapping_add(self.size) == next.start.as_u64()
+            && self.frame + self.size == next.frame;
+        if !contiguous || self.level != next.level || self.flags != next.flags {
+            return false;
+        }
+        self.size += next.size;
+        self.entries += next.entries;
+        true
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let page = match self.level {
+            1 => "4K",
+            2 => "2M",
+            _ => "1G"
+        };
+        write!(f, "L{} {:016x}-{:016x} -> {:#012x} {:>6} x {} {:?}",
+            self.level, self.start.as_u64(), self.start.as_u64().wrapping_add(self.size),
+            self.frame.as_u64(), self.entries, page, self.flags)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PageTableSummary {
+    // the level 4 table included
+    pub table_frames: usize,
+    pub pages_4k: usize,
+    pub pages_2m: usize,
+    pub pages_1g: usize
+}
+
+impl PageTableSummary {
+    pub fn mapped_bytes(&self) -> u64 {
+        self.pages_4k as u64 * entry_size(1) + self.pages_2m as u64 * entry_size(2)
+            + self.pages_1g as u64 * entry_size(3)
+    }
+}
+
+impl fmt::Display for PageTableSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} page table frames, {} 4K pages, {} 2M pages, {} 1G pages, {} KiB mapped",
+            self.table_frames, self.pages_4k, self.pages_2m, self.pages_1g,
+            self.mapped_bytes() / 1024)
+    }
+}
+
+struct Walker<F> {
+    physical_memory_offset: VirtAddr,
+    first: u64,
+    last: u64,
+    pending: Option<Mapping>,
+    summary: PageTableSummary,
+    visit: F
+}
+
+impl<F: FnMut(&Mapping)> Walker<F> {
+    unsafe fn walk(&mut self, table: &PageTable, level: u8, base: u64) {
+        self.summary.table_frames += 1;
+        let size = entry_size(level);
+        for (idx, entry) in table.iter().enumerate() {
+            let start = sign_extend(base + idx as u64 * size);
+            if start > self.last || start + (size - 1) < self.first {
+                continue;
+            }
+            let flags = entry.flags();
+            if !flags.contains(PageTableFlags::PRESENT) {
+                continue;
+            }
+            if level > 1 && !flags.contains(PageTableFlags::HUGE_PAGE) {
+                let table_addr = self.physical_memory_offset + entry.addr().as_u64();
+                self.walk(&*table_addr.as_ptr::<PageTable>(), level - 1, start);
+                continue;
+            }
+            match level {
+                1 => self.summary.pages_4k += 1,
+                2 => self.summary.pages_2m += 1,
+                _ => self.summary.pages_1g += 1
+            }
+            self.push(Mapping {
+                level,
+                start: VirtAddr::new(start),
+                size,
+                frame: entry.addr(),
+                flags: flags - IGNORED_FLAGS,
+                entries: 1
+            });
+        }
+    }
+
+    fn push(&mut self, mapping: Mapping) {
+        if let Some(pending) = &mut self.pending {
+            if pending.extend(&mapping) {
+                return;
+            }
+            (self.visit)(pending);
+        }
+        self.pending = Some(mapping);
+    }
+
+    fn finish(mut self) -> PageTableSummary {
+        if let Some(pending) = self.pending.take() {
+            (self.visit)(&pending);
+        }
+        self.summary
+    }
+}
+
+// walks the active page tables and hands every merged run of present leaf
+// entries inside `range` to `visit`, in address order
+pub unsafe fn walk_page_tables(
+    range: impl RangeBounds<VirtAddr>,
+    physical_memory_offset: VirtAddr,
+    visit: impl FnMut(&Mapping)
+) -> PageTableSummary {
+    let first = match range.start_bound() {
+        Bound::Included(addr) => addr.as_u64(),
+        Bound::Excluded(addr) => addr.as_u64() + 1,
+        Bound::Unbounded => 0
+    };
+    let last = match range.end_bound() {
+        Bound::Included(addr) => addr.as_u64(),
+        Bound::Excluded(addr) => addr.as_u64().saturating_sub(1),
+        Bound::Unbounded => u64::MAX
+    };
+    let mut walker = Walker {
+        physical_memory_offset,
+        first,
+        last,
+        pending: None,
+        summary: PageTableSummary::default(),
+        visit
+    };
+    if first <= last {
+        let (level_4_table_frame, _) = Cr3::read();
+        let table_addr = physical_memory_offset + level_4_table_frame.start_address().as_u64();
+        walker.walk(&*table_addr.as_ptr::<PageTable>(), 4, 0);
+    }
+    walker.finish()
+}
+
+const fn entry_size(level: u8) -> u64 {
+    4096 << (9 * (level as u64 - 1))
+}
+
+fn sign_extend(addr: u64) -> u64 {
+    VirtAddr::new_truncate(addr).as_u64()
+}
@@ -0,0 +1,206 @@
+use x86_64::{PhysAddr, VirtAddr, instructions::tlb, structures::paging::{
+    OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+    FrameAllocator, FrameDeallocator, Mapper, Translate,
+    mapper::{FlagUpdateError, MapToError, MappedFrame, MapperFlush, TranslateResult}, page::PageRange,
+    page_table::PageTableEntry
+}};
+
+// past this many pages a full flush is cheaper than one invlpg per page
+const FLUSH_ALL_THRESHOLD: usize = 32;
+
+// the flags `protect_range` is allowed to change
+pub const PROTECTION_FLAGS: PageTableFlags = PageTableFlags::WRITABLE
+    .union(PageTableFlags::NO_EXECUTE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+
+// collects the pages touched by a range operation and flushes them once it
+// is dropped, also when the operation bails out early
+struct FlushBatch {
+    pages: [Page<Size4KiB>; FLUSH_ALL_THRESHOLD],
+    len: usize,
+    flush_all: bool
+}
+
+impl FlushBatch {
+    fn new() -> Self {
+        FlushBatch {
+            pages: [Page::containing_address(VirtAddr::zero()); FLUSH_ALL_THRESHOLD],
+            len: 0,
+            flush_all: false
+        }
+    }
+
+    fn add(&mut self, flush: MapperFlush<Size4KiB>, page: Page<Size4KiB>) {
+        flush.ignore();
+        if self.len == FLUSH_ALL_THRESHOLD {
+            self.flush_all = true;
+        } else {
+            self.pages[self.len] = page;
+            self.len += 1;
+        }
+    }
+}
+
+impl Drop for FlushBatch {
+    fn drop(&mut self) {
+        if self.flush_all {
+            tlb::flush_all();
+        } else {
+            for page in self.pages[..self.len].iter() {
+                tlb::flush(page.start_address());
+            }
+        }
+    }
+}
+
+// backs every page with a fresh frame, nothing stays mapped on failure
+pub fn map_range<A>(
+    pages: PageRange<Size4KiB>,
+    flags: PageTableFlags,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut A
+) -> Result<(), MapToError<Size4KiB>>
+where
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>
+{
+    let mut batch = FlushBatch::new();
+    for page in pages {
+        let frame = match frame_allocator.allocate_frame() {
+            Some(frame) => frame,
+            None => {
+                drop(batch);
+                unmap_range(Page::range(pages.start, page), mapper, frame_allocator);
+                return Err(MapToError::FrameAllocationFailed);
+            }
+        };
+        match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => batch.add(flush, page),
+            Err(err) => {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                drop(batch);
+                unmap_range(Page::range(pages.start, page), mapper, frame_allocator);
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+// unmaps the range and gives its frames back, pages that are not mapped are
+// skipped; returns how many frames were freed
+pub fn unmap_range(
+    pages: PageRange<Size4KiB>,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>
+) -> usize {
+    let mut batch = FlushBatch::new();
+    let mut frames = [None; FLUSH_ALL_THRESHOLD];
+    let mut freed = 0;
+    for page in pages {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            batch.add(flush, page);
+            // a frame may only be reused once no TLB entry points at it anymore
+            frames[freed % FLUSH_ALL_THRESHOLD] = Some(frame);
+            freed += 1;
+            if freed % FLUSH_ALL_THRESHOLD == 0 {
+                drop(batch);
+                batch = FlushBatch::new();
+                free_frames(&mut frames, frame_deallocator);
+            }
+        }
+    }
+    drop(batch);
+    free_frames(&mut frames, frame_deallocator);
+    freed
+}
+
+fn free_frames(
+    frames: &mut [Option<PhysFrame<Size4KiB>>],
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>
+) {
+    for frame in frames.iter_mut().filter_map(Option::take) {
+        unsafe { frame_deallocator.deallocate_frame(frame) };
+    }
+}
+
+// sets and clears WRITABLE, NO_EXECUTE and USER_ACCESSIBLE on mapped 4KiB pages
+pub fn protect_range(
+    pages: PageRange<Size4KiB>,
+    set: PageTableFlags,
+    clear: PageTableFlags,
+    mapper: &mut OffsetPageTable
+) -> Result<(), FlagUpdateError> {
+    assert!(PROTECTION_FLAGS.contains(set | clear), "only protection flags can be changed");
+    let mut batch = FlushBatch::new();
+    for page in pages {
+        let flags = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { frame: MappedFrame::Size4KiB(_), flags, .. } => flags,
+            TranslateResult::Mapped { .. } => return Err(FlagUpdateError::ParentEntryHugePage),
+            _ => return Err(FlagUpdateError::PageNotMapped)
+        };
+        if set.contains(PageTableFlags::USER_ACCESSIBLE) {
+            // the parents have to allow user access too, whatever else they
+            // restrict keeps applying to everything below them
+            let [p4, p3, p2] = parent_flags(mapper, page);
+            let user = PageTableFlags::USER_ACCESSIBLE;
+            unsafe {
+                mapper.set_flags_p4_entry(page, p4 | user)?.ignore();
+                mapper.set_flags_p3_entry(page, p3 | user)?.ignore();
+                mapper.set_flags_p2_entry(page, p2 | user)?.ignore();
+            }
+        }
+        let flush = unsafe { mapper.update_flags(page, (flags | set) - clear)? };
+        batch.add(flush, page);
+    }
+    Ok(())
+}
+
+// the P4, P3 and P2 entries above a page that `translate` found mapped
+fn parent_flags(mapper: &mut OffsetPageTable, page: Page<Size4KiB>) -> [PageTableFlags; 3] {
+    let phys_offset = mapper.phys_offset();
+    let table = |entry: &PageTableEntry| -> &PageTable {
+        let table_ptr: *const PageTable = (phys_offset + entry.addr().as_u64()).as_ptr();
+        unsafe { &*table_ptr }
+    };
+    let p4_entry = mapper.level_4_table()[page.p4_index()].clone();
+    let p3_entry = &table(&p4_entry)[page.p3_index()];
+    let p2_entry = &table(p3_entry)[page.p2_index()];
+    [p4_entry.flags(), p3_entry.flags(), p2_entry.flags()]
+}
+
+// maps device memory at the same virtual address, uncached, and returns
+// that address
+pub fn identity_map_mmio(
+    start: PhysAddr,
+    size: u64,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>
+) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+        | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+    let first = PhysFrame::<Size4KiB>::containing_address(start);
+    let last = PhysFrame::containing_address(start + size.max(1) - 1u64);
+    let mut batch = FlushBatch::new();
+    for frame in PhysFrame::range_inclusive(first, last) {
+        let page = Page::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+        let flush = match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => flush,
+            // mapped before, possibly cached, so only the flags are updated
+            Err(MapToError::PageAlreadyMapped(mapped)) if mapped == frame => unsafe {
+                mapper.update_flags(page, flags).map_err(|_| MapToError::PageAlreadyMapped(mapped))?
+            },
+            Err(err) => return Err(err)
+        };
+        batch.add(flush, page);
+    }
+    Ok(VirtAddr::new(start.as_u64()))
+}
+
+// every page that overlaps `size` bytes from `start`
+pub fn page_range(start: VirtAddr, size: u64) -> PageRange<Size4KiB> {
+    let first = Page::containing_address(start);
+    if size == 0 {
+        return Page::range(first, first);
+    }
+    Page::range(first, Page::containing_address(start + (size - 1)) + 1)
+}
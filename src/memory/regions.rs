@@ -0,0 +1,247 @@
+use core::fmt;
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegion, MemoryRegionType};
+use conquer_once::spin::OnceCell;
+use x86_64::PhysAddr;
+
+use crate::serial_print;
+
+const MAX_REGIONS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Usable,
+    // taken by the bootloader for itself, frame zero included
+    InUse,
+    Kernel,
+    PageTables,
+    Bootloader,
+    BootInfo,
+    AcpiReclaimable,
+    AcpiNvs,
+    Bad,
+    // reserved by the firmware, including types the bootloader does not know
+    Reserved
+}
+
+const KINDS: [RegionKind; 10] = [
+    RegionKind::Usable, RegionKind::InUse, RegionKind::Kernel, RegionKind::PageTables,
+    RegionKind::Bootloader, RegionKind::BootInfo, RegionKind::AcpiReclaimable,
+    RegionKind::AcpiNvs, RegionKind::Bad, RegionKind::Reserved
+];
+
+impl RegionKind {
+    pub fn classify(region_type: MemoryRegionType) -> Option<RegionKind> {
+        Some(match region_type {
+            MemoryRegionType::Usable => RegionKind::Usable,
+            MemoryRegionType::InUse | MemoryRegionType::FrameZero => RegionKind::InUse,
+            MemoryRegionType::Kernel | MemoryRegionType::KernelStack => RegionKind::Kernel,
+            MemoryRegionType::PageTable => RegionKind::PageTables,
+            MemoryRegionType::Bootloader | MemoryRegionType::Package => RegionKind::Bootloader,
+            MemoryRegionType::BootInfo => RegionKind::BootInfo,
+            MemoryRegionType::AcpiReclaimable => RegionKind::AcpiReclaimable,
+            MemoryRegionType::AcpiNvs => RegionKind::AcpiNvs,
+            MemoryRegionType::BadMemory => RegionKind::Bad,
+            MemoryRegionType::Reserved | MemoryRegionType::UnknownUefi(_)
+                | MemoryRegionType::UnknownBios(_) => RegionKind::Reserved,
+            MemoryRegionType::Empty => return None
+        })
+    }
+
+    // reserved ranges may just as well be device memory, so they do not count
+    pub fn is_ram(self) -> bool {
+        !matches!(self, RegionKind::Reserved | RegionKind::Bad)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            RegionKind::Usable => "usable",
+            RegionKind::InUse => "in use",
+            RegionKind::Kernel => "kernel",
+            RegionKind::PageTables => "page tables",
+            RegionKind::Bootloader => "bootloader",
+            RegionKind::BootInfo => "boot info",
+            RegionKind::AcpiReclaimable => "ACPI reclaimable",
+            RegionKind::AcpiNvs => "ACPI NVS",
+            RegionKind::Bad => "bad",
+            RegionKind::Reserved => "reserved"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: PhysAddr,
+    pub end: PhysAddr,
+    pub kind: RegionKind
+}
+
+impl Region {
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, addr: PhysAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#012x}-{:#012x} {:>10} KiB {}",
+            self.start.as_u64(), self.end.as_u64(), self.size() / 1024, self.kind.name())
+    }
+}
+
+// the bootloader's map sorted, with neighbours of the same kind merged
+pub struct PhysicalMemoryMap {
+    regions: [Region; MAX_REGIONS],
+    len: usize
+}
+
+impl PhysicalMemoryMap {
+    pub fn new<'a>(memory_map: impl IntoIterator<Item = &'a MemoryRegion>) -> Self {
+        let empty = Region { start: PhysAddr::zero(), end: PhysAddr::zero(), kind: RegionKind::Reserved };
+        let mut map = PhysicalMemoryMap { regions: [empty; MAX_REGIONS], len: 0 };
+        for region in memory_map {
+            let kind = match RegionKind::classify(region.region_type) {
+                Some(kind) if !region.range.is_empty() => kind,
+                _ => continue
+            };
+            map.insert(Region {
+                start: PhysAddr::new(region.range.start_addr()),
+                end: PhysAddr::new(region.range.end_addr()),
+                kind
+            });
+        }
+        map.merge();
+        map
+    }
+
+    fn insert(&mut self, region: Region) {
+        assert!(self.len < MAX_REGIONS, "too many physical memory regions");
+        let idx = self.iter().position(|other| other.start > region.start).unwrap_or(self.len);
+        self.regions.copy_within(idx..self.len, idx + 1);
+        self.regions[idx] = region;
+        self.len += 1;
+    }
+
+    fn merge(&mut self) {
+        let mut merged = 0;
+        for idx in 0..self.len {
+            let region = self.regions[idx];
+            if merged > 0 {
+                let last = &mut self.regions[merged - 1];
+                if last.kind == region.kind && last.end == region.start {
+                    last.end = region.end;
+                    continue;
+                }
+            }
+            self.regions[merged] = region;
+            merged += 1;
+        }
+        self.len = merged;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.regions[..self.len].iter()
+    }
+
+    pub fn find(&self, addr: PhysAddr) -> Option<Region> {
+        self.iter().find(|region| region.contains(addr)).copied()
+    }
+
+    pub fn total(&self, kind: RegionKind) -> u64 {
+        self.iter().filter(|region| region.kind == kind).map(Region::size).sum()
+    }
+
+    pub fn total_ram(&self) -> u64 {
+        self.iter().filter(|region| region.kind.is_ram()).map(Region::size).sum()
+    }
+}
+
+impl fmt::Display for PhysicalMemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for region in self.iter() {
+            writeln!(f, "{}", region)?;
+        }
+        for &kind in KINDS.iter() {
+            let total = self.total(kind);
+            if total > 0 {
+                writeln!(f, "{:>16}: {} KiB", kind.name(), total / 1024)?;
+            }
+        }
+        writeln!(f, "{} MiB RAM, {} MiB usable",
+            self.total_ram() >> 20, self.total(RegionKind::Usable) >> 20)
+    }
+}
+
+static PHYSICAL_MEMORY_MAP: OnceCell<PhysicalMemoryMap> = OnceCell::uninit();
+
+pub fn init(memory_map: &'static MemoryMap) {
+    PHYSICAL_MEMORY_MAP.try_init_once(|| PhysicalMemoryMap::new(memory_map.iter()))
+        .expect("regions::init should be called only once");
+}
+
+pub fn physical_memory_map() -> Option<&'static PhysicalMemoryMap> {
+    PHYSICAL_MEMORY_MAP.try_get().ok()
+}
+
+pub fn find(addr: PhysAddr) -> Option<Region> {
+    physical_memory_map()?.find(addr)
+}
+
+// anything the firmware does not report as RAM has to be treated as device
+// memory and mapped uncached
+pub fn is_ram(addr: PhysAddr) -> bool {
+    find(addr).map_or(false, |region| region.kind.is_ram())
+}
+
+pub fn dump() {
+    if let Some(map) = physical_memory_map() {
+        serial_print!("{}", map);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bootloader::bootinfo::{FrameRange, MemoryRegion, MemoryRegionType};
+    use x86_64::PhysAddr;
+    use super::{PhysicalMemoryMap, RegionKind};
+
+    fn region(start: u64, end: u64, region_type: MemoryRegionType) -> MemoryRegion {
+        MemoryRegion { range: FrameRange::new(start, end), region_type }
+    }
+
+    #[test_case]
+    fn adjacent_regions_of_one_kind_are_merged() {
+        let regions = [
+            region(0x10_0000, 0x20_0000, MemoryRegionType::Kernel),
+            region(0x0, 0x1000, MemoryRegionType::FrameZero),
+            region(0x1000, 0x9_f000, MemoryRegionType::Usable),
+            region(0x20_0000, 0x21_0000, MemoryRegionType::KernelStack),
+            region(0x21_0000, 0x80_0000, MemoryRegionType::Usable),
+            region(0x80_0000, 0x90_0000, MemoryRegionType::Usable)
+        ];
+        let map = PhysicalMemoryMap::new(regions.iter());
+        let kinds = [RegionKind::InUse, RegionKind::Usable, RegionKind::Kernel, RegionKind::Usable];
+        assert_eq!(map.iter().count(), kinds.len());
+        assert!(map.iter().zip(kinds.iter()).all(|(region, &kind)| region.kind == kind));
+        assert_eq!(map.find(PhysAddr::new(0x85_0000)).unwrap().start, PhysAddr::new(0x21_0000));
+        assert_eq!(map.total(RegionKind::Kernel), 0x11_0000);
+    }
+
+    #[test_case]
+    fn holes_and_reserved_ranges_are_not_ram() {
+        let regions = [
+            region(0x0, 0x9_f000, MemoryRegionType::Usable),
+            region(0xf_0000, 0x10_0000, MemoryRegionType::Reserved),
+            region(0x10_0000, 0x20_0000, MemoryRegionType::AcpiReclaimable)
+        ];
+        let map = PhysicalMemoryMap::new(regions.iter());
+        assert!(map.find(PhysAddr::new(0xa_0000)).is_none());
+        assert!(!map.find(PhysAddr::new(0xf_8000)).unwrap().kind.is_ram());
+        assert!(map.find(PhysAddr::new(0x18_0000)).unwrap().kind.is_ram());
+        assert_eq!(map.total_ram(), 0x9_f000 + 0x10_0000);
+    }
+}
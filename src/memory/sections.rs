@@ -0,0 +1,141 @@
+use core::slice;
+
+use x86_64::{VirtAddr, structures::paging::{Page, PageTableFlags, Size4KiB, mapper::FlagUpdateError, page::PageRange}};
+
+use super::{kernel_memory, paging};
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+#[repr(C)]
+struct ElfHeader {
+    ident: [u8; 16],
+    kind: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16
+}
+
+#[repr(C)]
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64
+}
+
+extern "C" {
+    // defined by the linker, the headers are loaded along with the first segment
+    static __ehdr_start: ElfHeader;
+}
+
+#[derive(Debug)]
+pub enum ProtectError {
+    NotElf,
+    NoKernelMemory,
+    Paging(FlagUpdateError)
+}
+
+impl From<FlagUpdateError> for ProtectError {
+    fn from(err: FlagUpdateError) -> Self {
+        ProtectError::Paging(err)
+    }
+}
+
+// the section headers are not loaded, but the linker groups sections into
+// segments by permission: .text is RX, .rodata R and .data/.bss RW
+fn program_headers() -> Result<&'static [ProgramHeader], ProtectError> {
+    let header = unsafe { &__ehdr_start };
+    if header.ident[..4] != ELF_MAGIC
+        || header.phentsize as usize != core::mem::size_of::<ProgramHeader>() {
+        return Err(ProtectError::NotElf);
+    }
+    let start = header as *const ElfHeader as *const u8;
+    let headers = unsafe { start.add(header.phoff as usize) } as *const ProgramHeader;
+    Ok(unsafe { slice::from_raw_parts(headers, header.phnum as usize) })
+}
+
+fn loaded_segments() -> Result<impl Iterator<Item = &'static ProgramHeader> + Clone, ProtectError> {
+    Ok(program_headers()?.iter().filter(|header| header.kind == PT_LOAD && header.memsz > 0))
+}
+
+fn segment_pages(segment: &ProgramHeader) -> PageRange<Size4KiB> {
+    paging::page_range(VirtAddr::new(segment.vaddr), segment.memsz)
+}
+
+// the flags to set on `page`, a page shared by two segments gets the
+// permissions of both, None if no segment covers it
+fn page_flags<'a>(
+    segments: impl Iterator<Item = &'a ProgramHeader>, page: Page<Size4KiB>
+) -> Option<PageTableFlags> {
+    let mut covered = false;
+    let mut set = PageTableFlags::NO_EXECUTE;
+    for segment in segments {
+        let pages = segment_pages(segment);
+        if page < pages.start || page >= pages.end {
+            continue;
+        }
+        covered = true;
+        if segment.flags & PF_W != 0 {
+            set |= PageTableFlags::WRITABLE;
+        }
+        if segment.flags & PF_X != 0 {
+            set -= PageTableFlags::NO_EXECUTE;
+        }
+    }
+    if covered { Some(set) } else { None }
+}
+
+// remaps the kernel image W^X, the flags only change at segment boundaries,
+// so every run of pages between them is updated at once
+pub fn protect_kernel_sections() -> Result<(), ProtectError> {
+    let segments = loaded_segments()?;
+    let boundaries = || segments.clone().flat_map(|segment| {
+        let pages = segment_pages(segment);
+        [pages.start, pages.end]
+    });
+    let (mut at, end) = match (boundaries().min(), boundaries().max()) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return Ok(())
+    };
+
+    let mut memory = kernel_memory().ok_or(ProtectError::NoKernelMemory)?;
+    let mut protect = |pages, set: PageTableFlags| {
+        let clear = (PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE) - set;
+        paging::protect_range(pages, set, clear, &mut memory.mapper)
+    };
+    let mut run: Option<(Page<Size4KiB>, PageTableFlags)> = None;
+    while at < end {
+        let next = boundaries().filter(|&page| page > at).min().unwrap_or(end);
+        let flags = page_flags(segments.clone(), at);
+        match run {
+            Some((_, run_flags)) if Some(run_flags) == flags => {}
+            _ => {
+                if let Some((start, run_flags)) = run.take() {
+                    protect(Page::range(start, at), run_flags)?;
+                }
+                run = flags.map(|flags| (at, flags));
+            }
+        }
+        at = next;
+    }
+    if let Some((start, flags)) = run {
+        protect(Page::range(start, end), flags)?;
+    }
+    Ok(())
+}
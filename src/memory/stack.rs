@@ -0,0 +1,139 @@
+use x86_64::{VirtAddr, structures::paging::{Page, PageSize, Size4KiB, FrameAllocator, Mapper, PageTableFlags, mapper::MapToError}};
+
+use super::{guard::GuardRegion, kernel_memory, try_kernel_memory, paging, KernelMemory};
+
+pub const KERNEL_STACKS_START: u64 = 0x_5555_0000_0000;
+// every stack owns a slot of this many pages and sits at its top, the rest
+// of the slot is never mapped and serves as the guard
+const SLOT_PAGES: u64 = 64;
+const MAX_STACKS: usize = 256;
+pub const MAX_STACK_PAGES: u64 = SLOT_PAGES - 1;
+pub const KERNEL_STACKS_SIZE: u64 = MAX_STACKS as u64 * SLOT_PAGES * Size4KiB::SIZE;
+
+#[derive(Clone, Copy)]
+struct Slot {
+    name: &'static str,
+    pages: u64,
+    // dropped while the kernel memory was held, the pages are still mapped
+    stale: bool
+}
+
+static SLOTS: spin::Mutex<[Option<Slot>; MAX_STACKS]> = spin::Mutex::new([None; MAX_STACKS]);
+
+#[derive(Debug)]
+pub enum StackError {
+    TooLarge,
+    OutOfSlots,
+    NoKernelMemory,
+    Map(MapToError<Size4KiB>)
+}
+
+impl From<MapToError<Size4KiB>> for StackError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        StackError::Map(err)
+    }
+}
+
+pub struct KernelStack {
+    slot: usize,
+    pages: u64
+}
+
+impl KernelStack {
+    pub fn allocate(name: &'static str, pages: u64) -> Result<KernelStack, StackError> {
+        if pages == 0 || pages > MAX_STACK_PAGES {
+            return Err(StackError::TooLarge);
+        }
+        let mut memory = kernel_memory().ok_or(StackError::NoKernelMemory)?;
+        release_stale_slots(&mut memory);
+        let slot = take_slot(Slot { name, pages, stale: false }).ok_or(StackError::OutOfSlots)?;
+        // mapped from the top down, so dropping a partly mapped stack undoes
+        // exactly what was done
+        let mut stack = KernelStack { slot, pages: 0 };
+        let KernelMemory { mapper, frame_allocator } = &mut *memory;
+        let top_page = Page::<Size4KiB>::containing_address(stack.top() - 1u64);
+        for i in 0..pages {
+            let frame = frame_allocator.allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+            unsafe {
+                mapper.map_to(top_page - i, frame, flags, frame_allocator)?.flush();
+            }
+            stack.pages += 1;
+        }
+        Ok(stack)
+    }
+
+    // exclusive, this is what goes into rsp or the TSS
+    pub fn top(&self) -> VirtAddr {
+        slot_start(self.slot + 1)
+    }
+
+    pub fn bottom(&self) -> VirtAddr {
+        self.top() - self.pages * Size4KiB::SIZE
+    }
+
+    pub fn size(&self) -> u64 {
+        self.pages * Size4KiB::SIZE
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        // whoever drops the stack may hold the kernel memory, the next
+        // `allocate` unmaps it then
+        match try_kernel_memory() {
+            Some(mut memory) => {
+                unmap_slot(self.slot, self.pages, &mut memory);
+                SLOTS.lock()[self.slot] = None;
+            }
+            None => {
+                if let Some(slot) = SLOTS.lock()[self.slot].as_mut() {
+                    slot.pages = self.pages;
+                    slot.stale = true;
+                }
+            }
+        }
+    }
+}
+
+fn unmap_slot(slot: usize, pages: u64, memory: &mut KernelMemory) {
+    let KernelMemory { mapper, frame_allocator } = memory;
+    let bottom = slot_start(slot + 1) - pages * Size4KiB::SIZE;
+    paging::unmap_range(paging::page_range(bottom, pages * Size4KiB::SIZE), mapper, frame_allocator);
+}
+
+fn release_stale_slots(memory: &mut KernelMemory) {
+    let mut slots = SLOTS.lock();
+    for (idx, slot) in slots.iter_mut().enumerate() {
+        if let Some(stack) = slot.filter(|stack| stack.stale) {
+            unmap_slot(idx, stack.pages, memory);
+            *slot = None;
+        }
+    }
+}
+
+fn take_slot(stack: Slot) -> Option<usize> {
+    let mut slots = SLOTS.lock();
+    let idx = slots.iter().position(Option::is_none)?;
+    slots[idx] = Some(stack);
+    Some(idx)
+}
+
+fn slot_start(slot: usize) -> VirtAddr {
+    VirtAddr::new(KERNEL_STACKS_START + slot as u64 * SLOT_PAGES * Size4KiB::SIZE)
+}
+
+// the unmapped part of the slot below the stack that `addr` falls into
+pub(super) fn find_guard(addr: VirtAddr) -> Option<GuardRegion> {
+    let offset = addr.as_u64().checked_sub(KERNEL_STACKS_START)?;
+    let slot = (offset / (SLOT_PAGES * Size4KiB::SIZE)) as usize;
+    let stack = (*SLOTS.try_lock()?.get(slot)?)?;
+    let start = slot_start(slot);
+    let end = slot_start(slot + 1) - stack.pages * Size4KiB::SIZE;
+    if addr < end {
+        Some(GuardRegion { name: stack.name, start, end })
+    } else {
+        None
+    }
+}
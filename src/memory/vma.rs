@@ -0,0 +1,250 @@
+use core::fmt;
+
+use x86_64::{VirtAddr, structures::paging::PageTableFlags};
+
+use crate::serial_print;
+
+// `allocate` hands out ranges from this window, everything else has to be
+// reserved at a fixed address
+pub const ALLOC_START: u64 = 0x_6000_0000_0000;
+pub const ALLOC_END: u64 = 0x_7000_0000_0000;
+const MAX_VMAS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    // address space only, whoever owns it maps the pages
+    Reserved,
+    // the page fault handler backs pages on first touch
+    Demand,
+    Mapped,
+    Guard
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vma {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    pub flags: PageTableFlags,
+    pub kind: VmaKind,
+    pub name: &'static str
+}
+
+impl Vma {
+    const EMPTY: Vma = Vma {
+        start: VirtAddr::zero(),
+        end: VirtAddr::zero(),
+        flags: PageTableFlags::empty(),
+        kind: VmaKind::Reserved,
+        name: ""
+    };
+
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+impl fmt::Display for Vma {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let writable = self.flags.contains(PageTableFlags::WRITABLE);
+        let executable = !self.flags.contains(PageTableFlags::NO_EXECUTE);
+        let user = self.flags.contains(PageTableFlags::USER_ACCESSIBLE);
+        let kind = match self.kind {
+            VmaKind::Reserved => "reserved",
+            VmaKind::Demand => "demand",
+            VmaKind::Mapped => "mapped",
+            VmaKind::Guard => "guard"
+        };
+        write!(f, "{:012x}-{:012x} {}{}{}{} {:>8} {:<8} {}",
+            self.start.as_u64(), self.end.as_u64(),
+            if self.kind == VmaKind::Guard { '-' } else { 'r' },
+            if writable { 'w' } else { '-' },
+            if executable && self.kind != VmaKind::Guard { 'x' } else { '-' },
+            if user { 'u' } else { 'k' },
+            self.size() / 1024, kind, self.name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    InvalidRange,
+    Overlap(Vma),
+    NoSpace,
+    TooManyAreas
+}
+
+// kept sorted by start address, it has to work before the heap exists
+pub struct VmaList {
+    areas: [Vma; MAX_VMAS],
+    len: usize
+}
+
+impl VmaList {
+    pub const fn new() -> Self {
+        VmaList { areas: [Vma::EMPTY; MAX_VMAS], len: 0 }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.areas[..self.len].iter()
+    }
+
+    pub fn find(&self, addr: VirtAddr) -> Option<Vma> {
+        self.iter().find(|vma| vma.contains(addr)).copied()
+    }
+
+    pub fn reserve(&mut self, vma: Vma) -> Result<Vma, VmaError> {
+        if vma.end <= vma.start {
+            return Err(VmaError::InvalidRange);
+        }
+        if let Some(other) = self.iter().find(|other| other.start < vma.end && vma.start < other.end) {
+            return Err(VmaError::Overlap(*other));
+        }
+        if self.len == MAX_VMAS {
+            return Err(VmaError::TooManyAreas);
+        }
+        let idx = self.iter().position(|other| other.start > vma.start).unwrap_or(self.len);
+        self.areas.copy_within(idx..self.len, idx + 1);
+        self.areas[idx] = vma;
+        self.len += 1;
+        Ok(vma)
+    }
+
+    // first fit in the window between `window_start` and `window_end`
+    pub fn find_free(&self, size: u64, align: u64, window_start: u64, window_end: u64)
+        -> Option<VirtAddr> {
+        assert!(align.is_power_of_two());
+        let mut candidate = align_up(window_start, align);
+        for vma in self.iter() {
+            if vma.end.as_u64() <= candidate {
+                continue;
+            }
+            if vma.start.as_u64() >= window_end {
+                break;
+            }
+            if candidate.checked_add(size)? <= vma.start.as_u64() {
+                break;
+            }
+            candidate = align_up(vma.end.as_u64(), align);
+        }
+        if candidate.checked_add(size)? <= window_end {
+            Some(VirtAddr::new(candidate))
+        } else {
+            None
+        }
+    }
+
+    pub fn release(&mut self, start: VirtAddr) -> Option<Vma> {
+        let idx = self.iter().position(|vma| vma.start == start)?;
+        let vma = self.areas[idx];
+        self.areas.copy_within(idx + 1..self.len, idx);
+        self.len -= 1;
+        Some(vma)
+    }
+
+    pub fn set_kind(&mut self, start: VirtAddr, kind: VmaKind) -> Option<Vma> {
+        let vma = self.areas[..self.len].iter_mut().find(|vma| vma.start == start)?;
+        vma.kind = kind;
+        Some(*vma)
+    }
+}
+
+static VMAS: spin::Mutex<VmaList> = spin::Mutex::new(VmaList::new());
+
+pub fn reserve(
+    name: &'static str, start: VirtAddr, size: u64, flags: PageTableFlags, kind: VmaKind
+) -> Result<Vma, VmaError> {
+    let end = start.as_u64().checked_add(size).ok_or(VmaError::InvalidRange)?;
+    let end = VirtAddr::try_new(end).map_err(|_| VmaError::InvalidRange)?;
+    VMAS.lock().reserve(Vma { start, end, flags, kind, name })
+}
+
+// finds a free range of `size` bytes aligned to `align` and reserves it
+pub fn allocate(
+    name: &'static str, size: u64, align: u64, flags: PageTableFlags, kind: VmaKind
+) -> Result<Vma, VmaError> {
+    let mut vmas = VMAS.lock();
+    let start = vmas.find_free(size, align, ALLOC_START, ALLOC_END).ok_or(VmaError::NoSpace)?;
+    vmas.reserve(Vma { start, end: start + size, flags, kind, name })
+}
+
+pub fn release(start: VirtAddr) -> Option<Vma> {
+    VMAS.lock().release(start)
+}
+
+pub fn set_kind(start: VirtAddr, kind: VmaKind) -> Option<Vma> {
+    VMAS.lock().set_kind(start, kind)
+}
+
+// fault handlers call this, so it must not wait for the lock
+pub fn find(addr: VirtAddr) -> Option<Vma> {
+    VMAS.try_lock()?.find(addr)
+}
+
+pub fn dump() {
+    serial_print!("{}", Maps);
+}
+
+// the whole list in /proc/maps style, one area per line
+pub struct Maps;
+
+impl fmt::Display for Maps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for vma in VMAS.lock().iter() {
+            writeln!(f, "{}", vma)?;
+        }
+        Ok(())
+    }
+}
+
+const fn align_up(addr: u64, align: u64) -> u64 {
+    (addr + align - 1) & !(align - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use x86_64::{VirtAddr, structures::paging::PageTableFlags};
+    use super::{Vma, VmaError, VmaKind, VmaList};
+
+    fn vma(start: u64, end: u64) -> Vma {
+        Vma {
+            start: VirtAddr::new(start),
+            end: VirtAddr::new(end),
+            flags: PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            kind: VmaKind::Reserved,
+            name: "test"
+        }
+    }
+
+    #[test_case]
+    fn overlapping_reservation_is_rejected() {
+        let mut list = VmaList::new();
+        list.reserve(vma(0x1000, 0x3000)).unwrap();
+        assert_eq!(list.reserve(vma(0x2000, 0x4000)), Err(VmaError::Overlap(vma(0x1000, 0x3000))));
+        assert!(list.reserve(vma(0x3000, 0x4000)).is_ok());
+        assert_eq!(list.reserve(vma(0x5000, 0x5000)), Err(VmaError::InvalidRange));
+    }
+
+    #[test_case]
+    fn free_range_respects_alignment() {
+        let mut list = VmaList::new();
+        list.reserve(vma(0x10000, 0x11000)).unwrap();
+        list.reserve(vma(0x13000, 0x20000)).unwrap();
+        let window = (0x10000, 0x40000);
+        assert_eq!(list.find_free(0x2000, 0x1000, window.0, window.1), Some(VirtAddr::new(0x11000)));
+        assert_eq!(list.find_free(0x2000, 0x4000, window.0, window.1), Some(VirtAddr::new(0x20000)));
+        assert_eq!(list.find_free(0x30000, 0x1000, window.0, window.1), None);
+    }
+
+    #[test_case]
+    fn released_range_is_reused() {
+        let mut list = VmaList::new();
+        list.reserve(vma(0x1000, 0x2000)).unwrap();
+        list.reserve(vma(0x2000, 0x3000)).unwrap();
+        assert_eq!(list.release(VirtAddr::new(0x1000)), Some(vma(0x1000, 0x2000)));
+        assert_eq!(list.find_free(0x1000, 0x1000, 0x1000, 0x3000), Some(VirtAddr::new(0x1000)));
+        assert_eq!(list.find(VirtAddr::new(0x2800)), Some(vma(0x2000, 0x3000)));
+    }
+}
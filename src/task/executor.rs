@@ -4,10 +4,10 @@ use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
 use crossbeam_queue::ArrayQueue;
 use x86_64::instructions::interrupts;
 
-use super::{TaskId, Task};
+use super::{TaskId, Task, TaskBox};
 
 pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
+    tasks: BTreeMap<TaskId, TaskBox>,
     task_queue: Arc<ArrayQueue<TaskId>>,
     waker_cache: BTreeMap<TaskId, Waker>
 }
@@ -23,7 +23,7 @@ impl Executor {
 
     pub fn spawn(&mut self, task: Task) {
         let task_id = task.task_id;
-        if self.tasks.insert(task_id, task).is_some() {
+        if self.tasks.insert(task_id, TaskBox::new(task)).is_some() {
             panic!("task with the same ID has been already in tasks");
         } else {
             self.task_queue.push(task_id)
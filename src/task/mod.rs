@@ -2,9 +2,15 @@ pub mod simple_executor;
 pub mod keyboard;
 pub mod executor;
 
-use core::{pin::Pin, future::Future, task::{Context, Poll}, sync::atomic::AtomicU64};
+use core::{pin::Pin, future::Future, ops::{Deref, DerefMut}, task::{Context, Poll}, sync::atomic::{AtomicU64, AtomicUsize, Ordering}};
 use alloc::boxed::Box;
 
+static LIVE_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn task_count() -> usize {
+    LIVE_TASKS.load(Ordering::Relaxed)
+}
+
 pub struct Task {
     task_id: TaskId,
     future: Pin<Box<dyn Future<Output = ()>>>
@@ -12,6 +18,7 @@ pub struct Task {
 
 impl Task {
     pub fn new(future: impl Future<Output = ()> + 'static) -> Self {
+        LIVE_TASKS.fetch_add(1, Ordering::Relaxed);
         Task {
             task_id: TaskId::new(),
             future: Box::pin(future)
@@ -24,6 +31,69 @@ impl Task {
 
 }
 
+impl Drop for Task {
+    fn drop(&mut self) {
+        LIVE_TASKS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// how the executor keeps a task, in the slab cache for tasks when the slab
+// allocator backs the heap
+pub(crate) struct TaskBox {
+    #[cfg(feature = "alloc-slab")]
+    task: core::ptr::NonNull<Task>,
+    #[cfg(not(feature = "alloc-slab"))]
+    task: Box<Task>
+}
+
+impl TaskBox {
+    #[cfg(feature = "alloc-slab")]
+    pub(crate) fn new(task: Task) -> Self {
+        let task = crate::allocator::alloc_cached(task).unwrap_or_else(|_| {
+            alloc::alloc::handle_alloc_error(core::alloc::Layout::new::<Task>())
+        });
+        TaskBox { task }
+    }
+
+    #[cfg(not(feature = "alloc-slab"))]
+    pub(crate) fn new(task: Task) -> Self {
+        TaskBox { task: Box::new(task) }
+    }
+}
+
+impl Deref for TaskBox {
+    type Target = Task;
+
+    #[cfg(feature = "alloc-slab")]
+    fn deref(&self) -> &Task {
+        unsafe { self.task.as_ref() }
+    }
+
+    #[cfg(not(feature = "alloc-slab"))]
+    fn deref(&self) -> &Task {
+        &self.task
+    }
+}
+
+impl DerefMut for TaskBox {
+    #[cfg(feature = "alloc-slab")]
+    fn deref_mut(&mut self) -> &mut Task {
+        unsafe { self.task.as_mut() }
+    }
+
+    #[cfg(not(feature = "alloc-slab"))]
+    fn deref_mut(&mut self) -> &mut Task {
+        &mut self.task
+    }
+}
+
+#[cfg(feature = "alloc-slab")]
+impl Drop for TaskBox {
+    fn drop(&mut self) {
+        drop(unsafe { crate::allocator::free_cached(self.task) });
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
 
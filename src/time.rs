@@ -0,0 +1,50 @@
+pub mod pit;
+
+use core::{sync::atomic::{AtomicU64, Ordering}, time::Duration};
+
+pub const DEFAULT_FREQUENCY: u32 = 1000;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+// kept separately from the ticks so changing the frequency does not
+// rescale the time that already passed
+static ELAPSED_NANOS: AtomicU64 = AtomicU64::new(0);
+// the PIT comes out of the firmware at ~18.2 Hz
+static TICK_NANOS: AtomicU64 = AtomicU64::new(tick_nanos(0x1_0000));
+
+const fn tick_nanos(divisor: u32) -> u64 {
+    divisor as u64 * NANOS_PER_SEC / pit::BASE_FREQUENCY as u64
+}
+
+pub fn set_frequency(frequency: u32) {
+    let divisor = pit::set_frequency(frequency);
+    TICK_NANOS.store(tick_nanos(divisor), Ordering::Relaxed);
+}
+
+// what the PIT actually runs at, rounded to whole hertz
+pub fn frequency() -> u64 {
+    NANOS_PER_SEC / TICK_NANOS.load(Ordering::Relaxed)
+}
+
+// called from the timer interrupt only
+pub(crate) fn tick() {
+    ELAPSED_NANOS.fetch_add(TICK_NANOS.load(Ordering::Relaxed), Ordering::Relaxed);
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime() -> Duration {
+    Duration::from_nanos(ELAPSED_NANOS.load(Ordering::Relaxed))
+}
+
+#[test_case]
+fn divisor_is_clamped_to_the_counter_range() {
+    assert_eq!(pit::divisor(1000), 1193);
+    assert_eq!(pit::divisor(1), 0x1_0000);
+    assert_eq!(pit::divisor(0), 0x1_0000);
+    assert_eq!(pit::divisor(u32::MAX), 1);
+}
@@ -0,0 +1,40 @@
+use x86_64::instructions::{interrupts, port::Port};
+
+// the input clock of the 8253/8254
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_0: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+// channel 0, low byte then high byte, mode 2 (rate generator), binary
+const CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+const CHANNEL_0_LATCH: u8 = 0b0000_0000;
+
+// a reload value of 0 counts 65536 input cycles
+pub fn divisor(frequency: u32) -> u32 {
+    (BASE_FREQUENCY / frequency.max(1)).clamp(1, 0x1_0000)
+}
+
+// returns the reload value, the frequency the PIT ends up running at is
+// `BASE_FREQUENCY / divisor`
+pub fn set_frequency(frequency: u32) -> u32 {
+    let divisor = divisor(frequency);
+    let reload = divisor as u16;
+    interrupts::without_interrupts(|| unsafe {
+        Port::<u8>::new(COMMAND).write(CHANNEL_0_RATE_GENERATOR);
+        let mut data = Port::<u8>::new(CHANNEL_0);
+        data.write(reload as u8);
+        data.write((reload >> 8) as u8);
+    });
+    divisor
+}
+
+// the current count of channel 0, it runs down from the reload value
+pub fn read_count() -> u16 {
+    interrupts::without_interrupts(|| unsafe {
+        Port::<u8>::new(COMMAND).write(CHANNEL_0_LATCH);
+        let mut data = Port::<u8>::new(CHANNEL_0);
+        let low = data.read();
+        let high = data.read();
+        u16::from_le_bytes([low, high])
+    })
+}
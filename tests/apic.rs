@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{panic::PanicInfo, sync::atomic::{AtomicUsize, Ordering}};
+use blog_os::{acpi, apic::{self, local::LocalApic}, interrupts::irq::{self, IrqStatus}, memory::{self, regions, bitmap::BitmapFrameAllocator}};
+use bootloader::{entry_point, BootInfo};
+use x86_64::{VirtAddr, instructions::port::Port};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    regions::init(&boot_info.memory_map);
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(physical_memory_offset) };
+    let frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    apic::init().expect("APIC initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn madt_describes_the_apics() {
+    let offset = memory::kernel_memory().unwrap().mapper.phys_offset();
+    let madt = unsafe { acpi::find_madt(offset) }.unwrap();
+    assert_eq!(madt.local_apic, LocalApic::base_address());
+    assert!(madt.io_apics().next().is_some());
+    // qemu wires the PIT to GSI 2
+    assert_eq!(madt.isa_route(0).gsi, 2);
+}
+
+#[test_case]
+fn legacy_pic_is_masked() {
+    assert!(apic::local_apic().is_some());
+    let masks = unsafe { [Port::<u8>::new(0x21).read(), Port::<u8>::new(0xa1).read()] };
+    assert_eq!(masks, [0xff, 0xff]);
+}
+
+static TIMER_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn count_timer(_irq: u8) -> IrqStatus {
+    TIMER_CALLS.fetch_add(1, Ordering::Relaxed);
+    IrqStatus::NotMine
+}
+
+#[test_case]
+fn timer_interrupts_arrive_through_the_io_apic() {
+    let id = irq::register(0, "apic test", count_timer).unwrap();
+    // other interrupts may end a hlt too, but only the timer counts
+    for _ in 0..1000 {
+        if TIMER_CALLS.load(Ordering::Relaxed) >= 3 {
+            break;
+        }
+        x86_64::instructions::hlt();
+    }
+    irq::unregister(id);
+    assert!(TIMER_CALLS.load(Ordering::Relaxed) >= 3);
+}
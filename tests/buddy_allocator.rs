@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use blog_os::memory::buddy::BuddyFrameAllocator;
+use bootloader::{entry_point, BootInfo};
+use x86_64::{VirtAddr, structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size2MiB, Size4KiB}};
+
+entry_point!(main);
+
+static FRAME_ALLOCATOR: spin::Mutex<Option<BuddyFrameAllocator>> = spin::Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn contiguous_range() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free = allocator.free_frames();
+    let range = allocator.allocate_contiguous(5).unwrap();
+    assert_eq!(range.end - range.start, 8);
+    assert_eq!(range.start.start_address().as_u64() % (8 * 4096), 0);
+    assert_eq!(allocator.free_frames(), free - 8);
+    unsafe { allocator.deallocate_contiguous(range) };
+    assert_eq!(allocator.free_frames(), free);
+}
+
+#[test_case]
+fn huge_frame() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let frame: PhysFrame<Size2MiB> = allocator.allocate_frame().unwrap();
+    assert!(frame.start_address().is_aligned(2u64 * 1024 * 1024));
+    unsafe { allocator.deallocate_frame(frame) };
+}
+
+#[test_case]
+fn buddies_merge() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free = allocator.free_frames();
+    let largest = allocator.largest_free_order();
+    let first: PhysFrame<Size4KiB> = allocator.allocate_frame().unwrap();
+    let second: PhysFrame<Size4KiB> = allocator.allocate_frame().unwrap();
+    unsafe {
+        allocator.deallocate_frame(first);
+        allocator.deallocate_frame(second);
+    }
+    assert_eq!(allocator.free_frames(), free);
+    assert_eq!(allocator.largest_free_order(), largest);
+}
+
+#[test_case]
+fn empty_range_is_refused() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free = allocator.free_frames();
+    assert!(allocator.allocate_contiguous(0).is_none());
+    assert_eq!(allocator.free_frames(), free);
+}
@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use blog_os::{exit_qemu, memory::buddy::BuddyFrameAllocator, serial_print, serial_println, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use x86_64::{VirtAddr, structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB}};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("buddy_double_free::second_free_is_rejected...\t");
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    let frame: PhysFrame<Size4KiB> = frame_allocator.allocate_frame().unwrap();
+    let free = frame_allocator.free_frames();
+    unsafe {
+        frame_allocator.deallocate_frame(frame);
+        frame_allocator.deallocate_frame(frame);
+    }
+    serial_println!("[failed]");
+    serial_println!("free frames went from {} to {}", free, frame_allocator.free_frames());
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+use core::{arch::asm, fmt::{self, Write}, panic::PanicInfo};
+use blog_os::{exit_qemu, memory::{self, bitmap::BitmapFrameAllocator}, serial_print, serial_println, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+// canonical, but nothing is mapped there
+const BAD_RBP: u64 = 0x4444_4444_0000;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("corrupt_backtrace::report_survives_a_bad_rbp...\t");
+    blog_os::gdt::init();
+    blog_os::interrupts::init_idt();
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(physical_memory_offset) };
+    let frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    // a page fault while walking the chain would end in the page fault
+    // handler's hlt_loop instead of the panic below
+    unsafe { asm!("mov rbp, {}", "ud2", in(reg) BAD_RBP, options(noreturn)) };
+}
+
+struct Buffer {
+    bytes: [u8; 2048],
+    len: usize
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut buffer = Buffer { bytes: [0; 2048], len: 0 };
+    let _ = write!(buffer, "{}", info);
+    let report = core::str::from_utf8(&buffer.bytes[..buffer.len]).unwrap_or("");
+    let expected = ["EXCEPTION: INVALID OPCODE (#UD, vector 6)", "rbp 0000444444440000", "backtrace:"];
+    if expected.iter().all(|line| report.contains(line)) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("{}", report);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use blog_os::{allocator, exit_qemu, memory::{self, bitmap::BitmapFrameAllocator, vma}, serial_print, serial_println, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+// not part of any VMA and never mapped
+const UNRESERVED: u64 = 0x_7777_0000_0000;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("demand_fault_outside_vma::fault_is_fatal...\t");
+    blog_os::init();
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(physical_memory_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    let addr = VirtAddr::new(UNRESERVED);
+    assert!(vma::find(addr).is_none());
+    unsafe { addr.as_mut_ptr::<u64>().write_volatile(42) };
+
+    serial_println!("[failed]");
+    serial_println!("the write at {:?} was backed", addr);
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
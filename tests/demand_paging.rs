@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use blog_os::{allocator, memory::{self, demand, bitmap::BitmapFrameAllocator}};
+use bootloader::{entry_point, BootInfo};
+use x86_64::{VirtAddr, structures::paging::{PageTableFlags, Translate}};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(physical_memory_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn is_mapped(addr: VirtAddr) -> bool {
+    memory::kernel_memory().unwrap().mapper.translate_addr(addr).is_some()
+}
+
+#[test_case]
+fn pages_are_backed_on_first_touch() {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let vma = demand::reserve("demand test", 16 * 4096, flags).unwrap();
+    let first = vma.start;
+    let last = vma.end - 8u64;
+    assert!(!is_mapped(first));
+    assert!(!is_mapped(last));
+
+    unsafe { last.as_mut_ptr::<u64>().write_volatile(42) };
+    assert!(is_mapped(last));
+    assert!(!is_mapped(first));
+    assert_eq!(unsafe { last.as_ptr::<u64>().read_volatile() }, 42);
+}
+
+#[test_case]
+fn fresh_pages_are_zeroed() {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let vma = demand::reserve("demand test", 4096, flags).unwrap();
+    let bytes = vma.start.as_ptr::<u8>();
+    assert!((0..4096).all(|i| unsafe { bytes.add(i).read_volatile() } == 0));
+}
@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use blog_os::{allocator, exit_qemu, memory::{self, demand, bitmap::BitmapFrameAllocator}, serial_print, serial_println, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use x86_64::{VirtAddr, structures::paging::PageTableFlags};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("demand_write_read_only::write_is_fatal...\t");
+    blog_os::init();
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(physical_memory_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    let vma = demand::reserve("read-only demand test", 4096, flags).unwrap();
+    // reading backs the page, the write after it hits a read-only mapping
+    assert_eq!(unsafe { vma.start.as_ptr::<u64>().read_volatile() }, 0);
+    unsafe { vma.start.as_mut_ptr::<u64>().write_volatile(42) };
+
+    serial_println!("[failed]");
+    serial_println!("the write to the read-only page at {:?} went through", vma.start);
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+use core::{arch::asm, fmt::{self, Write}, panic::PanicInfo};
+use blog_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("exceptions::invalid_opcode_report...\t");
+    blog_os::gdt::init();
+    blog_os::interrupts::init_idt();
+    unsafe { asm!("ud2") };
+    serial_println!("[failed]");
+    serial_println!("Execution continued after ud2");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+// keeps the start of the panic message, that is where the report begins
+struct Buffer {
+    bytes: [u8; 2048],
+    len: usize
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut buffer = Buffer { bytes: [0; 2048], len: 0 };
+    let _ = write!(buffer, "{}", info);
+    let report = core::str::from_utf8(&buffer.bytes[..buffer.len]).unwrap_or("");
+    let expected = ["EXCEPTION: INVALID OPCODE (#UD, vector 6)", "error code: none", "rax ", "rip ", "cr0 ", "backtrace:"];
+    if expected.iter().all(|line| report.contains(line)) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("{}", report);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
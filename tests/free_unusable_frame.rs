@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use blog_os::{exit_qemu, memory::bitmap::BitmapFrameAllocator, serial_print, serial_println, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use x86_64::{PhysAddr, VirtAddr, structures::paging::{FrameDeallocator, PhysFrame}};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("free_unusable_frame::vga_frame_is_rejected...\t");
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    let free = frame_allocator.free_frames();
+    // the VGA buffer, never part of a usable region
+    unsafe { frame_allocator.deallocate_frame(PhysFrame::containing_address(PhysAddr::new(0xb8000))) };
+    serial_println!("[failed]");
+    serial_println!("free frames went from {} to {}", free, frame_allocator.free_frames());
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use blog_os::{serial_print, serial_println, exit_qemu, QemuExitCode, allocator, memory::{self, guard, bitmap::BitmapFrameAllocator}};
+use bootloader::{entry_point, BootInfo};
+use x86_64::{VirtAddr, registers::control::Cr2, structures::idt::{self, InterruptStackFrame, PageFaultErrorCode}};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("guard_page::heap_underflow...\t");
+    blog_os::gdt::init();
+    init_test_idt();
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(physical_memory_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+
+    let below_heap = (allocator::HEAP_START - 8) as *mut u64;
+    unsafe { below_heap.write_volatile(0) };
+    panic!("Execution continued after writing below the heap")
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+lazy_static! {
+    static ref TEST_IDT: idt::InterruptDescriptorTable = {
+        let mut idt = idt::InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: PageFaultErrorCode
+) {
+    match guard::find_guard(Cr2::read()) {
+        Some(guard) if guard.name == "heap" => {
+            serial_println!("[ok]");
+            exit_qemu(QemuExitCode::Success);
+        }
+        _ => {
+            serial_println!("[failed]");
+            serial_println!("fault at {:?} outside the heap guard", Cr2::read());
+            exit_qemu(QemuExitCode::Failed);
+        }
+    }
+    loop {}
+}
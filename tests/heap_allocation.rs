@@ -4,9 +4,9 @@
 #![test_runner(blog_os::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
-use core::panic::PanicInfo;
+use core::{panic::PanicInfo, sync::atomic::{AtomicUsize, Ordering}};
 use alloc::{boxed::Box, vec::Vec};
-use blog_os::{memory, allocator::{self, HEAP_SIZE}};
+use blog_os::{memory::{self, bitmap::BitmapFrameAllocator}, allocator};
 use bootloader::{entry_point, BootInfo};
 use x86_64::VirtAddr;
 
@@ -19,9 +19,12 @@ fn main(boot_info: &'static BootInfo) -> ! {
 
     let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(physical_memory_offset) };
-    let mut frame_allocator = memory::BootInfoFrameAllocator::init(&boot_info.memory_map);
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
 
     test_main();
     loop {}
@@ -49,12 +52,111 @@ fn large_vec() {
     assert_eq!(vec.iter().sum::<i32>(), (1000 - 1) * 1000 / 2);
 }
 
+#[test_case]
+fn freed_allocations_are_no_leaks() {
+    allocator::assert_no_leaks(|| {
+        let value = Box::new(114);
+        let vec: Vec<i32> = (0..1000).collect();
+        assert_eq!(*value + vec[514], 628);
+    });
+}
+
+#[cfg(feature = "heap-leak-tracker")]
+#[test_case]
+fn leak_tracker_reports_forgotten_allocations() {
+    assert_eq!(allocator::count_leaks(|| drop(Box::new(514))), 0);
+    let leaks = allocator::count_leaks(|| {
+        core::mem::forget(Box::new(114));
+        core::mem::forget(Box::new([0u8; 64]));
+        drop(Box::new(514));
+    });
+    assert_eq!(leaks, 2);
+}
+
 #[test_case]
 fn many_boxes() {
+    for i in 0..allocator::HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(i, *x);
+    }
+}
+
+// the bump allocator only reuses memory once every allocation is freed
+#[cfg(not(feature = "alloc-bump"))]
+#[test_case]
+fn many_boxes_long_lived() {
     let long_lived_value = Box::new(1);
-    for i in 0..HEAP_SIZE {
+    for i in 0..allocator::HEAP_SIZE {
         let x = Box::new(i);
         assert_eq!(i, *x);
     }
     assert_eq!(*long_lived_value, 1);
 }
+
+#[cfg(not(feature = "alloc-external"))]
+#[test_case]
+fn heap_grows_on_demand() {
+    let committed = allocator::heap_committed();
+    let vec: Vec<u8> = alloc::vec![42; 2 * allocator::HEAP_SIZE];
+    assert!(vec.iter().all(|&x| x == 42));
+    assert!(allocator::heap_committed() > committed);
+    assert!(allocator::heap_committed() <= allocator::heap_limit());
+}
+
+// the debug layer pads allocations and holds frees back
+#[cfg(not(any(feature = "alloc-external", feature = "heap-debug")))]
+#[test_case]
+fn stats_track_allocations() {
+    let before = allocator::stats().counters;
+    let value = Box::new([0u8; 64]);
+    let during = allocator::stats().counters;
+    assert_eq!(during.allocations, before.allocations + 1);
+    assert_eq!(during.bytes_in_use, before.bytes_in_use + 64);
+    drop(value);
+    let after = allocator::stats().counters;
+    assert_eq!(after.frees, before.frees + 1);
+    assert_eq!(after.bytes_in_use, before.bytes_in_use);
+    assert!(after.peak_bytes_in_use >= during.bytes_in_use);
+}
+
+#[cfg(all(
+    any(feature = "alloc-linked-list", feature = "alloc-fixed-block", feature = "alloc-slab"),
+    not(feature = "heap-debug")
+))]
+#[test_case]
+fn realloc_within_size_class() {
+    let mut vec: Vec<u8> = Vec::with_capacity(10);
+    vec.extend_from_slice(&[1; 10]);
+    let ptr = vec.as_ptr();
+    vec.reserve_exact(6);
+    assert_eq!(vec.as_ptr(), ptr);
+    assert_eq!(vec, [1; 10]);
+}
+
+#[test_case]
+fn failed_try_reserve_runs_reclaimers() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    let id = allocator::oom::register_reclaimer("test", || {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        0
+    }).unwrap();
+    let mut vec: Vec<u8> = Vec::new();
+    assert!(vec.try_reserve(2 * allocator::HEAP_MAX_SIZE).is_err());
+    assert!(CALLS.load(Ordering::Relaxed) > 0);
+    allocator::oom::unregister_reclaimer(id);
+
+    vec.try_reserve(64).expect("small allocation failed after reclaiming");
+}
+
+#[cfg(feature = "alloc-slab")]
+#[test_case]
+fn spawned_tasks_live_in_the_task_cache() {
+    use blog_os::{allocator::slab::TASK_CACHE, task::{executor::Executor, Task}};
+
+    let before = allocator::slab_cache_stats(TASK_CACHE).objects_in_use;
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {}));
+    assert_eq!(allocator::slab_cache_stats(TASK_CACHE).objects_in_use, before + 1);
+    drop(executor);
+    assert_eq!(allocator::slab_cache_stats(TASK_CACHE).objects_in_use, before);
+}
@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{alloc::Layout, panic::PanicInfo};
+use alloc::{alloc::{alloc, dealloc}, boxed::Box};
+use blog_os::{memory::{self, bitmap::BitmapFrameAllocator}, allocator::{self, debug}};
+use bootloader::{entry_point, BootInfo};
+use x86_64::VirtAddr;
+
+extern crate alloc;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(physical_memory_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn flush_quarantine() {
+    for i in 0..debug::QUARANTINE_LEN {
+        drop(Box::new(i));
+    }
+}
+
+#[test_case]
+fn fresh_allocation_is_poisoned() {
+    let layout = Layout::from_size_align(48, 8).unwrap();
+    unsafe {
+        let ptr = alloc(layout);
+        assert!((0..48).all(|i| *ptr.add(i) == debug::ALLOC_POISON));
+        dealloc(ptr, layout);
+    }
+}
+
+#[test_case]
+fn double_free_is_detected() {
+    let layout = Layout::new::<u64>();
+    let violations = debug::violations();
+    unsafe {
+        let ptr = alloc(layout);
+        dealloc(ptr, layout);
+        dealloc(ptr, layout);
+    }
+    assert_eq!(debug::violations(), violations + 1);
+}
+
+#[test_case]
+fn layout_mismatch_is_detected() {
+    let violations = debug::violations();
+    unsafe {
+        let ptr = alloc(Layout::from_size_align(32, 8).unwrap());
+        dealloc(ptr, Layout::from_size_align(64, 8).unwrap());
+    }
+    assert_eq!(debug::violations(), violations + 1);
+}
+
+#[test_case]
+fn overflow_hits_red_zone() {
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let violations = debug::violations();
+    unsafe {
+        let ptr = alloc(layout);
+        *ptr.add(24) = 0;
+        dealloc(ptr, layout);
+    }
+    assert_eq!(debug::violations(), violations + 1);
+}
+
+#[test_case]
+fn write_after_free_is_detected() {
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    let violations = debug::violations();
+    unsafe {
+        let ptr = alloc(layout);
+        dealloc(ptr, layout);
+        // the block stays in quarantine, so it is still ours to scribble on
+        *ptr = 1;
+    }
+    flush_quarantine();
+    assert_eq!(debug::violations(), violations + 1);
+}
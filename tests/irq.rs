@@ -0,0 +1,139 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use core::{panic::PanicInfo, sync::atomic::{AtomicUsize, Ordering}};
+use alloc::sync::Arc;
+use spin::Mutex;
+use blog_os::{allocator, interrupts::irq::{self, IrqError, IrqStatus}, memory::{self, bitmap::BitmapFrameAllocator}};
+use bootloader::{entry_point, BootInfo};
+use x86_64::{VirtAddr, instructions::interrupts};
+
+entry_point!(main);
+
+// nothing is wired to IRQ 5 in qemu, the tests dispatch it by hand
+const TEST_IRQ: u8 = 5;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(physical_memory_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// goes through the handlers like a real interrupt, but sends no EOI that
+// could acknowledge an interrupt which is actually in service
+fn raise_test_irq() {
+    interrupts::without_interrupts(|| irq::dispatch(TEST_IRQ));
+}
+
+static FIRST: AtomicUsize = AtomicUsize::new(0);
+
+fn first_handler(_irq: u8) -> IrqStatus {
+    FIRST.fetch_add(1, Ordering::Relaxed);
+    IrqStatus::Handled
+}
+
+#[test_case]
+fn shared_line_runs_every_handler() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let first = irq::register(TEST_IRQ, "first", first_handler).unwrap();
+    let counter = calls.clone();
+    let second = irq::register_closure(TEST_IRQ, "second", move |irq| {
+        assert_eq!(irq, TEST_IRQ);
+        counter.fetch_add(1, Ordering::Relaxed);
+        IrqStatus::NotMine
+    }).unwrap();
+
+    let before = FIRST.load(Ordering::Relaxed);
+    raise_test_irq();
+    assert_eq!(FIRST.load(Ordering::Relaxed), before + 1);
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+    assert!(irq::unregister(first));
+    assert!(!irq::unregister(first));
+    raise_test_irq();
+    assert_eq!(FIRST.load(Ordering::Relaxed), before + 1);
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+    assert!(irq::unregister(second));
+}
+
+#[test_case]
+fn unclaimed_interrupt_is_counted() {
+    let unhandled = irq::unhandled_count();
+    raise_test_irq();
+    assert_eq!(irq::unhandled_count(), unhandled + 1);
+}
+
+#[test_case]
+fn registration_limits() {
+    assert_eq!(irq::register(irq::IRQ_COUNT as u8, "invalid", first_handler), Err(IrqError::InvalidIrq));
+    let mut ids = [None; 8];
+    let mut full = false;
+    for id in ids.iter_mut() {
+        match irq::register(TEST_IRQ, "filler", first_handler) {
+            Ok(handler) => *id = Some(handler),
+            Err(err) => {
+                assert_eq!(err, IrqError::LineFull);
+                full = true;
+                break;
+            }
+        }
+    }
+    assert!(full);
+    for id in ids.iter().flatten() {
+        assert!(irq::unregister(*id));
+    }
+}
+
+#[test_case]
+fn handler_can_unregister_itself() {
+    static ID: Mutex<Option<irq::IrqHandlerId>> = Mutex::new(None);
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let id = irq::register_closure(TEST_IRQ, "oneshot", move |_irq| {
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(id) = ID.lock().take() {
+            irq::unregister(id);
+        }
+        IrqStatus::Handled
+    }).unwrap();
+    *ID.lock() = Some(id);
+
+    raise_test_irq();
+    raise_test_irq();
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+    assert!(!irq::unregister(id));
+}
+
+#[test_case]
+fn stale_id_leaves_the_slot_alone() {
+    let old = irq::register(TEST_IRQ, "old", first_handler).unwrap();
+    assert!(irq::unregister(old));
+    // takes over the slot the old handler had
+    let new = irq::register(TEST_IRQ, "new", first_handler).unwrap();
+    assert!(!irq::unregister(old));
+
+    let before = FIRST.load(Ordering::Relaxed);
+    raise_test_irq();
+    assert_eq!(FIRST.load(Ordering::Relaxed), before + 1);
+    assert!(irq::unregister(new));
+}
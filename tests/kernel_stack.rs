@@ -0,0 +1,95 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use blog_os::{allocator, memory::{self, guard, bitmap::BitmapFrameAllocator, stack::{KernelStack, StackError, MAX_STACK_PAGES}}};
+use bootloader::{entry_point, BootInfo};
+use x86_64::{VirtAddr, structures::paging::Translate};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(physical_memory_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+    blog_os::gdt::init_stacks().expect("interrupt stack initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn is_mapped(addr: VirtAddr) -> bool {
+    memory::kernel_memory().unwrap().mapper.translate_addr(addr).is_some()
+}
+
+fn free_frames() -> usize {
+    memory::kernel_memory().unwrap().frame_allocator.free_frames()
+}
+
+#[test_case]
+fn stack_is_mapped_and_writable() {
+    let stack = KernelStack::allocate("test stack", 4).unwrap();
+    assert_eq!(stack.size(), 4 * 4096);
+    for addr in [stack.bottom(), stack.top() - 8u64].iter() {
+        unsafe { addr.as_mut_ptr::<u64>().write_volatile(42) };
+        assert_eq!(unsafe { addr.as_ptr::<u64>().read_volatile() }, 42);
+    }
+}
+
+#[test_case]
+fn page_below_stack_is_guard() {
+    let stack = KernelStack::allocate("test stack", 2).unwrap();
+    let below = stack.bottom() - 8u64;
+    assert!(!is_mapped(below));
+    assert_eq!(guard::find_guard(below).map(|guard| guard.name), Some("test stack"));
+    assert!(guard::find_guard(stack.bottom()).is_none());
+}
+
+#[test_case]
+fn dropped_stack_is_unmapped() {
+    // the first stack in a slot may need new page tables, which stay around
+    drop(KernelStack::allocate("test stack", 8).unwrap());
+    let free = free_frames();
+    let stack = KernelStack::allocate("test stack", 8).unwrap();
+    let bottom = stack.bottom();
+    assert!(free_frames() <= free - 8);
+    drop(stack);
+    assert!(!is_mapped(bottom));
+    assert!(guard::find_guard(bottom - 8u64).is_none());
+    assert_eq!(free_frames(), free);
+}
+
+#[test_case]
+fn stack_dropped_under_the_lock_is_unmapped_later() {
+    let stack = KernelStack::allocate("test stack", 4).unwrap();
+    let bottom = stack.bottom();
+    {
+        let _memory = memory::kernel_memory().unwrap();
+        drop(stack);
+    }
+    assert!(is_mapped(bottom));
+    let _other = KernelStack::allocate("test stack", 1).unwrap();
+    assert!(!is_mapped(bottom));
+}
+
+#[test_case]
+fn stacks_get_separate_slots() {
+    let stacks = [KernelStack::allocate("a", 1).unwrap(), KernelStack::allocate("b", 1).unwrap()];
+    assert_ne!(stacks[0].top(), stacks[1].top());
+    assert!(matches!(KernelStack::allocate("too big", MAX_STACK_PAGES + 1), Err(StackError::TooLarge)));
+}
@@ -0,0 +1,201 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{panic::PanicInfo, sync::atomic::{AtomicU64, Ordering}};
+use blog_os::memory::{self, inspect, paging, bitmap::BitmapFrameAllocator};
+use bootloader::{entry_point, BootInfo};
+use x86_64::{VirtAddr, PhysAddr, structures::paging::{Mapper, PageTableFlags, Translate, mapper::TranslateResult}};
+
+entry_point!(main);
+
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+static FRAME_ALLOCATOR: spin::Mutex<Option<BitmapFrameAllocator>> = spin::Mutex::new(None);
+
+// far away from the heap, the stacks and the vma window
+const TEST_RANGE_START: u64 = 0x_7100_0000_0000;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    PHYSICAL_MEMORY_OFFSET.store(boot_info.physical_memory_offset, Ordering::Relaxed);
+    let frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset())
+    };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+fn assert_matches_mapper(addr: VirtAddr) {
+    let offset = physical_memory_offset();
+    let mapper = unsafe { memory::init(offset) };
+    let translation = unsafe { memory::translate(addr, offset) }
+        .expect("address not mapped");
+    match mapper.translate(addr) {
+        TranslateResult::Mapped { frame, offset, .. } => {
+            assert_eq!(translation.phys_addr, frame.start_address() + offset);
+            assert_eq!(translation.page_size(), frame.size());
+        }
+        _ => panic!("mapper could not translate {:?}", addr),
+    }
+}
+
+#[test_case]
+fn translate_physical_memory_mapping() {
+    let offset = physical_memory_offset();
+    for &phys in &[0xb8000u64, 0x1000, 0x20_1234] {
+        let addr = offset + phys;
+        assert_matches_mapper(addr);
+        assert_eq!(unsafe { memory::translate_addr(addr, offset) }, Some(PhysAddr::new(phys)));
+    }
+}
+
+#[test_case]
+fn translate_code_and_stack() {
+    let local = 0u64;
+    assert_matches_mapper(VirtAddr::from_ptr(&local));
+    assert_matches_mapper(VirtAddr::new(physical_memory_offset as fn() -> VirtAddr as u64));
+}
+
+#[test_case]
+fn stack_is_writable() {
+    let local = 0u64;
+    let translation = unsafe { memory::translate(VirtAddr::from_ptr(&local), physical_memory_offset()) }
+        .unwrap();
+    assert!(translation.flags.contains(PageTableFlags::PRESENT | PageTableFlags::WRITABLE));
+}
+
+fn flags_of(addr: VirtAddr) -> Option<PageTableFlags> {
+    let mapper = unsafe { memory::init(physical_memory_offset()) };
+    match mapper.translate(addr) {
+        TranslateResult::Mapped { flags, .. } => Some(flags),
+        _ => None
+    }
+}
+
+#[test_case]
+fn unmap_range_returns_frames() {
+    let mut mapper = unsafe { memory::init(physical_memory_offset()) };
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frame_allocator = guard.as_mut().unwrap();
+    let start = VirtAddr::new(TEST_RANGE_START);
+    let pages = paging::page_range(start, 40 * 4096);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    // the first mapping may allocate page tables, which stay around
+    paging::map_range(pages, flags, &mut mapper, frame_allocator).unwrap();
+    paging::unmap_range(pages, &mut mapper, frame_allocator);
+    let free = frame_allocator.free_frames();
+
+    paging::map_range(pages, flags, &mut mapper, frame_allocator).unwrap();
+    assert_eq!(frame_allocator.free_frames(), free - 40);
+    let last = start + 40 * 4096u64 - 8u64;
+    unsafe { last.as_mut_ptr::<u64>().write_volatile(7) };
+    assert_eq!(unsafe { last.as_ptr::<u64>().read_volatile() }, 7);
+
+    assert_eq!(paging::unmap_range(pages, &mut mapper, frame_allocator), 40);
+    assert_eq!(frame_allocator.free_frames(), free);
+    assert!(flags_of(start).is_none());
+    assert!(flags_of(last).is_none());
+}
+
+#[test_case]
+fn protect_range_changes_flags() {
+    let mut mapper = unsafe { memory::init(physical_memory_offset()) };
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frame_allocator = guard.as_mut().unwrap();
+    let start = VirtAddr::new(TEST_RANGE_START);
+    let pages = paging::page_range(start, 2 * 4096);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    paging::map_range(pages, flags, &mut mapper, frame_allocator).unwrap();
+
+    paging::protect_range(pages, PageTableFlags::empty(), PageTableFlags::WRITABLE, &mut mapper).unwrap();
+    assert!(!flags_of(start + 4096u64).unwrap().contains(PageTableFlags::WRITABLE));
+    paging::protect_range(pages, PageTableFlags::WRITABLE, PageTableFlags::empty(), &mut mapper).unwrap();
+    assert!(flags_of(start).unwrap().contains(flags));
+
+    paging::unmap_range(pages, &mut mapper, frame_allocator);
+}
+
+#[test_case]
+fn user_access_keeps_parent_restrictions() {
+    let mut mapper = unsafe { memory::init(physical_memory_offset()) };
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frame_allocator = guard.as_mut().unwrap();
+    // a P2 table of its own, the restriction below must not leak into the
+    // other tests
+    let start = VirtAddr::new(TEST_RANGE_START + (1 << 30));
+    let pages = paging::page_range(start, 4096);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    paging::map_range(pages, flags, &mut mapper, frame_allocator).unwrap();
+    unsafe {
+        mapper.set_flags_p2_entry(pages.start, flags | PageTableFlags::NO_EXECUTE).unwrap().ignore();
+    }
+
+    paging::protect_range(pages, PageTableFlags::USER_ACCESSIBLE, PageTableFlags::empty(), &mut mapper).unwrap();
+    // the combined flags of all levels
+    let effective = unsafe { memory::translate(start, physical_memory_offset()) }.unwrap().flags;
+    assert!(effective.contains(PageTableFlags::USER_ACCESSIBLE));
+    assert!(effective.contains(PageTableFlags::NO_EXECUTE));
+
+    paging::unmap_range(pages, &mut mapper, frame_allocator);
+}
+
+#[test_case]
+fn mmio_is_identity_mapped_uncached() {
+    let mut mapper = unsafe { memory::init(physical_memory_offset()) };
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frame_allocator = guard.as_mut().unwrap();
+    // the local APIC, which nothing maps at this address yet
+    let lapic = PhysAddr::new(0xfee0_0000);
+    let addr = paging::identity_map_mmio(lapic, 4096, &mut mapper, frame_allocator).unwrap();
+    assert_eq!(addr.as_u64(), lapic.as_u64());
+    assert_eq!(unsafe { memory::translate_addr(addr, physical_memory_offset()) }, Some(lapic));
+    assert!(flags_of(addr).unwrap().contains(PageTableFlags::NO_CACHE));
+}
+
+#[test_case]
+fn page_table_walk_merges_runs() {
+    let mut mapper = unsafe { memory::init(physical_memory_offset()) };
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frame_allocator = guard.as_mut().unwrap();
+    let start = VirtAddr::new(TEST_RANGE_START);
+    let pages = paging::page_range(start, 4 * 4096);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    paging::map_range(pages, flags, &mut mapper, frame_allocator).unwrap();
+    let read_only = paging::page_range(start + 2 * 4096u64, 4096);
+    paging::protect_range(read_only, PageTableFlags::empty(), PageTableFlags::WRITABLE, &mut mapper).unwrap();
+
+    let mut runs = 0;
+    let mut entries = 0;
+    let summary = unsafe {
+        inspect::walk_page_tables(start..start + 4 * 4096u64, physical_memory_offset(), |mapping| {
+            runs += 1;
+            entries += mapping.entries;
+            assert_eq!(mapping.level, 1);
+            let writable = mapping.flags.contains(PageTableFlags::WRITABLE);
+            assert_eq!(writable, !(mapping.start <= read_only.start.start_address()
+                && read_only.start.start_address() < mapping.start + mapping.size));
+        })
+    };
+    assert_eq!(entries, 4);
+    assert!(runs >= 3);
+    assert_eq!(summary.pages_4k, 4);
+    assert_eq!(summary.pages_2m + summary.pages_1g, 0);
+    // the level 4 table and one table on each level below
+    assert_eq!(summary.table_frames, 4);
+
+    paging::unmap_range(pages, &mut mapper, frame_allocator);
+}
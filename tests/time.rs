@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{arch::x86_64::_rdtsc, panic::PanicInfo, time::Duration};
+use blog_os::time::{self, pit};
+use bootloader::{entry_point, BootInfo};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn wait_ticks(n: u64) {
+    let target = time::ticks() + n;
+    while time::ticks() < target {
+        x86_64::instructions::hlt();
+    }
+}
+
+// TSC cycles per timer tick, the TSC knows nothing about the PIT
+fn cycles_per_tick(ticks: u64) -> u64 {
+    // start right after a tick
+    wait_ticks(1);
+    let start = unsafe { _rdtsc() };
+    wait_ticks(ticks);
+    (unsafe { _rdtsc() } - start) / ticks
+}
+
+#[test_case]
+fn pit_counts_down_from_the_programmed_reload() {
+    let reload = pit::divisor(time::DEFAULT_FREQUENCY);
+    let mut first = None;
+    let mut changed = false;
+    for _ in 0..1000 {
+        let count = pit::read_count();
+        // the firmware default would count down from 65536
+        assert!(u32::from(count) <= reload, "count {} above reload {}", count, reload);
+        changed |= first.map_or(false, |first| first != count);
+        first.get_or_insert(count);
+    }
+    assert!(changed, "the PIT is not counting");
+}
+
+#[test_case]
+fn changing_the_frequency_changes_the_tick_rate() {
+    let fast = cycles_per_tick(20);
+    let uptime_before = time::uptime();
+    time::set_frequency(100);
+    let slow = cycles_per_tick(5);
+    time::set_frequency(time::DEFAULT_FREQUENCY);
+
+    // 1000 Hz against 100 Hz, with room for the jitter of an emulator
+    let ratio = slow * 10 / fast;
+    assert!((80..=120).contains(&ratio), "tick ratio {}.{}", ratio / 10, ratio % 10);
+    // uptime kept counting through the change, 5 slow ticks are 50 ms
+    assert!(time::uptime() - uptime_before >= Duration::from_millis(50));
+}
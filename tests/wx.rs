@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use blog_os::{serial_print, serial_println, exit_qemu, QemuExitCode, memory::{self, bitmap::BitmapFrameAllocator}};
+use bootloader::{entry_point, BootInfo};
+use x86_64::{VirtAddr, registers::control::Cr2, structures::idt::{self, InterruptStackFrame, PageFaultErrorCode}};
+
+entry_point!(main);
+
+static mut TEXT_ADDR: u64 = 0;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("wx::write_to_text...\t");
+    blog_os::cpu::init();
+    blog_os::gdt::init();
+    init_test_idt();
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(physical_memory_offset) };
+    let frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset)
+    };
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    let text = main as fn(&'static BootInfo) -> ! as *mut u8;
+    unsafe {
+        TEXT_ADDR = text as u64;
+        text.write_volatile(0xcc);
+    }
+    panic!("Execution continued after writing to .text")
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+lazy_static! {
+    static ref TEST_IDT: idt::InterruptDescriptorTable = {
+        let mut idt = idt::InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode
+) {
+    let expected = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if error_code.contains(expected) && Cr2::read().as_u64() == unsafe { TEXT_ADDR } {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("unexpected fault at {:?}: {:?}", Cr2::read(), error_code);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}